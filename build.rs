@@ -1,46 +1,146 @@
-use std::{env, fs, path::Path};
+use std::{env, fs, path::Path, process::Command};
+
+/// sqlean modules, each living in `sqlite3/ext/sqlean/src/<name>` and gated by `sqlean-<name>`.
+const SQLEAN_FAMILIES: &[&str] = &[
+    "text", "crypto", "define", "fileio", "fuzzy", "ipaddr", "math", "regexp", "stats", "time",
+    "unicode", "uuid", "vsv",
+];
+
+/// SQLite calls the sqlean sources make through `surveilr_<name>` instead, so the crate can
+/// collect per-function statistics (see `src/function_stats/interpose.rs`).
+const INTERPOSED_FUNCTIONS: &[&str] = &[
+    "sqlite3_create_function",
+    "sqlite3_create_function_v2",
+    "sqlite3_create_window_function",
+    "sqlite3_user_data",
+    "sqlite3_result_text",
+    "sqlite3_result_text64",
+    "sqlite3_result_blob",
+    "sqlite3_result_blob64",
+    "sqlite3_result_value",
+    "sqlite3_result_error",
+    "sqlite3_result_error_code",
+    "sqlite3_result_error_nomem",
+    "sqlite3_result_error_toobig",
+];
+
+fn feature_enabled(feature: &str) -> bool {
+    let var = format!(
+        "CARGO_FEATURE_{}",
+        feature.to_uppercase().replace('-', "_")
+    );
+    env::var_os(var).is_some()
+}
+
+/// Commit of the git checkout at `path`, falling back to the gitlink recorded by this repo when
+/// the submodule itself is not a checkout (e.g. a source tarball).
+fn git_sha(path: &str) -> Option<String> {
+    let run = |args: &[&str]| {
+        Command::new("git")
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8(output.stdout).ok())
+    };
+
+    if Path::new(path).join(".git").exists() {
+        if let Some(sha) = run(&["-C", path, "rev-parse", "HEAD"]) {
+            return Some(sha.trim().to_string());
+        }
+    }
+    // "<mode> commit <sha>\t<path>"
+    run(&["ls-tree", "HEAD", path])
+        .and_then(|line| line.split_whitespace().nth(2).map(str::to_string))
+}
+
+/// Build metadata exposed to the crate through `env!`, reported by `surveilr_functions` and the
+/// `*_debug()` functions.
+fn emit_build_info() {
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+    if Path::new(".git/HEAD").exists() {
+        println!("cargo:rerun-if-changed=.git/HEAD");
+    }
+
+    // honour SOURCE_DATE_EPOCH so reproducible builds get a stable date
+    let build_date = env::var("SOURCE_DATE_EPOCH")
+        .ok()
+        .and_then(|epoch| epoch.parse::<i64>().ok())
+        .and_then(|epoch| chrono::DateTime::from_timestamp(epoch, 0))
+        .unwrap_or_else(chrono::Utc::now);
+    println!(
+        "cargo:rustc-env=SURVEILR_BUILD_DATE={}",
+        build_date.to_rfc3339_opts(chrono::SecondsFormat::Secs, true)
+    );
+
+    let unknown = || "unknown".to_string();
+    println!(
+        "cargo:rustc-env=SURVEILR_GIT_SHA={}",
+        git_sha(".").unwrap_or_else(unknown)
+    );
+    println!(
+        "cargo:rustc-env=SURVEILR_SQLEAN_SHA={}",
+        git_sha("sqlite3/ext/sqlean").unwrap_or_else(unknown)
+    );
+}
 
 fn main() {
     println!("cargo:rerun-if-changed=build.rs");
+    emit_build_info();
 
     let src_dir = Path::new("sqlite3/ext/sqlean/src");
     let out_dir = env::var("OUT_DIR").unwrap();
 
+    let disabled_families: Vec<&str> = SQLEAN_FAMILIES
+        .iter()
+        .copied()
+        .filter(|family| !feature_enabled(&format!("sqlean-{family}")))
+        .collect();
+
+    // `cfg(sqlean)` is set when at least one sqlean family is compiled in
+    println!("cargo:rustc-check-cfg=cfg(sqlean)");
+    if disabled_families.len() < SQLEAN_FAMILIES.len() {
+        println!("cargo:rustc-cfg=sqlean");
+    }
+
+    if disabled_families.len() == SQLEAN_FAMILIES.len() {
+        // nothing to compile, only the Rust extensions were requested
+        return;
+    }
+
     let mut build = cc::Build::new();
+    let loadable_extension = env::var_os("CARGO_FEATURE_LOADABLE_EXTENSION").is_some();
+    if loadable_extension {
+        // ahead of sqlite3/include, so the shim shadows the real sqlite3ext.h
+        add_sqlite3_api_shim(&mut build, Path::new(&out_dir));
+    }
     build.include("sqlite3/include");
     build.include("sqlite3/ext/sqlean/src");
 
     // add subdirs for header files
-    find_header_dirs(&src_dir, &mut build);
+    find_header_dirs(src_dir, &mut build);
 
-    if cfg!(target_os = "windows") {
-        println!("cargo:warning=Excluding sqlite-lines on Windows");
-    } else {
-        build.include("sqlite3/ext/sqlite-lines");
-    }
+    if !loadable_extension {
+        // suppress multiple sqlite3_api definitions in the C files
+        // (dont really know the implication of these two macros, chatgpt'd it)
+        build.define("SQLITE_CORE", None);
+        build.define("SQLITE_API_VAR", None);
 
-    // suppress multiple sqlite3_api definitions in the C files
-    // (dont really know the implication of these two macros, chatgpt'd it)
-    build.define("SQLITE_CORE", None);
-    build.define("SQLITE_API_VAR", None);
+        // in loadable mode these go through the routines table, which the crate patches instead
+        for name in INTERPOSED_FUNCTIONS {
+            build.define(name, format!("surveilr_{}", name).as_str());
+        }
+    }
 
     // sqlean PCRE2 headers
-    build.define("HAVE_CONFIG_H", None);
-    build.define("PCRE2_CODE_UNIT_WIDTH", "8");
-    build.define("PCRE2_STATIC", None);
-    
-    build.define("LINK_SIZE", "2");
-    build.define("SUPPORT_UNICODE", None); 
-
-
-    // sqlite-url variables
-    build
-        .define("SQLITE_LINES_VERSION", "\"v0.1.0\"")
-        .define("SQLITE_LINES_DATE", "\"2024-11-28T11:36:54Z\"")
-        .define(
-            "SQLITE_LINES_SOURCE",
-            "\"19cf842b1a5f44a9c23ad0d396d167f004c6eb7f\"",
-        );
+    if !disabled_families.contains(&"regexp") {
+        build.define("HAVE_CONFIG_H", None);
+        build.define("PCRE2_CODE_UNIT_WIDTH", "8");
+        build.define("PCRE2_STATIC", None);
+
+        build.define("LINK_SIZE", "2");
+        build.define("SUPPORT_UNICODE", None);
+    }
 
     // handle BYTE_ORDER definition for Windows
     if cfg!(target_os = "windows") {
@@ -49,12 +149,7 @@ fn main() {
         build.define("BYTE_ORDER", Some("LITTLE_ENDIAN"));
     }
 
-    find_c_files(&src_dir, &mut build);
-    if cfg!(target_os = "windows") {
-        println!("cargo:warning=Excluding sqlite-lines on Windows");
-    } else {
-        build.file("sqlite3/ext/sqlite-lines/sqlite-lines.c");
-    }
+    find_c_files(src_dir, &disabled_families, &mut build);
 
     build.compile("sqlite3ext");
 
@@ -62,12 +157,51 @@ fn main() {
     println!("cargo:rustc-link-search=native={}", out_dir);
 }
 
-fn find_c_files(dir: &Path, build: &mut cc::Build) {
+/// Every sqlean entrypoint expands `SQLITE_EXTENSION_INIT1` into its own `sqlite3_api`
+/// definition, which clash when linked into one library. Shadows `sqlite3ext.h` with a header
+/// that turns it into the `extern` declaration of `SQLITE_EXTENSION_INIT3`, and compiles the one
+/// definition of the pointer. All entrypoints then store the same host routines in it.
+fn add_sqlite3_api_shim(build: &mut cc::Build, out_dir: &Path) {
+    let shim_dir = out_dir.join("sqlite3_api_shim");
+    fs::create_dir_all(&shim_dir).unwrap();
+
+    let manifest_dir = env::var("CARGO_MANIFEST_DIR").unwrap();
+    let real_header = Path::new(&manifest_dir).join("sqlite3/include/sqlite3ext.h");
+    let header = format!(
+        "#ifndef SURVEILR_SQLITE3EXT_SHIM_H\n\
+         #define SURVEILR_SQLITE3EXT_SHIM_H\n\
+         #include \"{}\"\n\
+         #undef SQLITE_EXTENSION_INIT1\n\
+         #define SQLITE_EXTENSION_INIT1 SQLITE_EXTENSION_INIT3\n\
+         #endif\n",
+        real_header.display().to_string().replace('\\', "/")
+    );
+    let source = "#include \"sqlite3ext.h\"\n\
+                  const sqlite3_api_routines *sqlite3_api = 0;\n";
+    write_if_changed(&shim_dir.join("sqlite3ext.h"), &header);
+    write_if_changed(&shim_dir.join("sqlite3_api.c"), source);
+
+    build.include(&shim_dir);
+    build.file(shim_dir.join("sqlite3_api.c"));
+}
+
+/// Writes `contents` to `path` unless it already holds them, keeping its mtime for `cc`.
+fn write_if_changed(path: &Path, contents: &str) {
+    if fs::read_to_string(path).ok().as_deref() != Some(contents) {
+        fs::write(path, contents).unwrap();
+    }
+}
+
+fn find_c_files(dir: &Path, disabled_families: &[&str], build: &mut cc::Build) {
     for entry in fs::read_dir(dir).unwrap() {
         let entry = entry.unwrap();
         let path = entry.path();
+        if is_disabled(&path, disabled_families) {
+            continue;
+        }
         if path.is_dir() {
-            find_c_files(&path, build);
+            // families are only matched against the top-level entries of sqlean/src
+            find_c_files(&path, &[], build);
         } else if path.extension().and_then(|ext| ext.to_str()) == Some("c") {
             println!("cargo:rerun-if-changed={}", path.display());
             build.file(path);
@@ -75,6 +209,21 @@ fn find_c_files(dir: &Path, build: &mut cc::Build) {
     }
 }
 
+/// Whether `path` belongs to a sqlean family whose feature is off: its `src/<family>` directory,
+/// its standalone `sqlite3-<family>.c` entrypoint, or the `sqlean.c` bundle that references all.
+fn is_disabled(path: &Path, disabled_families: &[&str]) -> bool {
+    let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+    if path.is_dir() {
+        return disabled_families.contains(&name);
+    }
+    if name == "sqlean.c" {
+        return !disabled_families.is_empty();
+    }
+    name.strip_prefix("sqlite3-")
+        .and_then(|name| name.strip_suffix(".c"))
+        .is_some_and(|family| disabled_families.contains(&family))
+}
+
 fn find_header_dirs(dir: &Path, build: &mut cc::Build) {
     for entry in fs::read_dir(dir).unwrap() {
         let entry = entry.unwrap();
@@ -0,0 +1,396 @@
+use rusqlite::{
+    ffi,
+    types::Value,
+    vtab::{self, eponymous_only_module, CreateVTab, IndexInfo, VTab, VTabCursor, VTabKind},
+    Connection, Result,
+};
+use std::collections::HashSet;
+use std::os::raw::c_int;
+
+use crate::{register_table_function, Families, Rows, TableFunction};
+
+const MODULE_NAME: &str = "surveilr_functions";
+
+/// One function or virtual-table module contributed by a family, as listed by `surveilr_functions`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct CatalogEntry {
+    name: String,
+    /// `scalar`, `aggregate`, `window` or `module`.
+    kind: String,
+    family: String,
+    /// Number of arguments, `-1` for variadic functions and `None` for modules.
+    narg: Option<i64>,
+    deterministic: bool,
+    direct_only: bool,
+    source: String,
+    version: String,
+}
+
+/// Raw `pragma_function_list` / `pragma_module_list` rows, compared before and after a family is
+/// registered to find out what it added.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum Registered {
+    Function {
+        name: String,
+        builtin: bool,
+        kind: String,
+        narg: i64,
+        flags: i64,
+    },
+    Module {
+        name: String,
+    },
+}
+
+fn snapshot(conn: &Connection) -> Result<HashSet<Registered>> {
+    let mut registered = HashSet::new();
+
+    let mut stmt =
+        conn.prepare("SELECT DISTINCT name, builtin, type, narg, flags FROM pragma_function_list")?;
+    let functions = stmt.query_map([], |row| {
+        Ok(Registered::Function {
+            name: row.get(0)?,
+            builtin: row.get(1)?,
+            kind: row.get(2)?,
+            narg: row.get(3)?,
+            flags: row.get(4)?,
+        })
+    })?;
+    for function in functions {
+        registered.insert(function?);
+    }
+
+    let mut stmt = conn.prepare("SELECT name FROM pragma_module_list")?;
+    let modules = stmt.query_map([], |row| Ok(Registered::Module { name: row.get(0)? }))?;
+    for module in modules {
+        registered.insert(module?);
+    }
+
+    Ok(registered)
+}
+
+/// Upstream repository and revision the family was built from.
+fn provenance(family: Families) -> (&'static str, &'static str) {
+    if family == Families::URL || family == Families::LINES {
+        (
+            "https://github.com/surveilr/surveilr-extensions",
+            env!("CARGO_PKG_VERSION"),
+        )
+    } else {
+        (
+            "https://github.com/surveilr/sqlean",
+            env!("SURVEILR_SQLEAN_SHA"),
+        )
+    }
+}
+
+/// Collects what each family registers on a connection so it can be listed by
+/// `surveilr_functions`.
+#[derive(Default)]
+pub(crate) struct Catalog {
+    entries: Vec<CatalogEntry>,
+}
+
+impl Catalog {
+    /// Starts from the entries already exposed on `conn`, so repeated `register_all` calls on the
+    /// same connection accumulate.
+    pub(crate) fn load(conn: &Connection) -> Result<Catalog> {
+        let exists: bool = conn.query_row(
+            "SELECT count(*) > 0 FROM pragma_module_list WHERE name = ?",
+            [MODULE_NAME],
+            |row| row.get(0),
+        )?;
+        if !exists {
+            return Ok(Catalog::default());
+        }
+
+        let mut stmt = conn.prepare(&format!(
+            "SELECT name, kind, family, narg, deterministic, direct_only, source, version FROM {}",
+            MODULE_NAME
+        ))?;
+        let entries = stmt
+            .query_map([], |row| {
+                Ok(CatalogEntry {
+                    name: row.get(0)?,
+                    kind: row.get(1)?,
+                    family: row.get(2)?,
+                    narg: row.get(3)?,
+                    deterministic: row.get(4)?,
+                    direct_only: row.get(5)?,
+                    source: row.get(6)?,
+                    version: row.get(7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Catalog { entries })
+    }
+
+    /// Runs `register` and records every function and module it added to `conn` under `family`.
+    pub(crate) fn record(
+        &mut self,
+        conn: &Connection,
+        name: &str,
+        family: Families,
+        register: impl FnOnce() -> Result<()>,
+    ) -> Result<()> {
+        let before = snapshot(conn)?;
+        register()?;
+        let after = snapshot(conn)?;
+
+        let (source, version) = provenance(family);
+        let family = name.to_lowercase();
+        let mut added: Vec<CatalogEntry> = after
+            .difference(&before)
+            .map(|registered| {
+                let (name, kind, narg, flags) = match registered {
+                    Registered::Function {
+                        name,
+                        kind,
+                        narg,
+                        flags,
+                        ..
+                    } => {
+                        let kind = match kind.as_str() {
+                            "a" => "aggregate",
+                            "w" => "window",
+                            _ => "scalar",
+                        };
+                        (name.clone(), kind, Some(*narg), *flags)
+                    }
+                    Registered::Module { name } => (name.clone(), "module", None, 0),
+                };
+                CatalogEntry {
+                    name,
+                    kind: kind.to_string(),
+                    family: family.clone(),
+                    narg,
+                    deterministic: flags & i64::from(ffi::SQLITE_DETERMINISTIC) != 0,
+                    direct_only: flags & i64::from(ffi::SQLITE_DIRECTONLY) != 0,
+                    source: source.to_string(),
+                    version: version.to_string(),
+                }
+            })
+            .collect();
+        added.sort_by(|a, b| (&a.name, &a.kind, a.narg).cmp(&(&b.name, &b.kind, b.narg)));
+
+        // re-registering a family replaces its previous entries
+        self.entries.retain(|entry| {
+            !added
+                .iter()
+                .any(|new| new.name == entry.name && new.narg == entry.narg)
+        });
+        self.entries.extend(added);
+        Ok(())
+    }
+
+    /// (Re)creates the `surveilr_functions` table on `conn` listing everything recorded so far,
+    /// along with `surveilr_build_info`.
+    pub(crate) fn register(self, conn: &Connection) -> Result<()> {
+        conn.create_module(
+            MODULE_NAME,
+            eponymous_only_module::<FunctionsTable>(),
+            Some(self.entries),
+        )?;
+        register_table_function(conn, "surveilr_build_info", BuildInfo)
+    }
+}
+
+/// `surveilr_build_info`: one `(key, value)` row per fact about this build, the crate
+/// `version`, the `git_sha` of this repository and `sqlean_sha` of the vendored sqlean sources
+/// (`unknown` when they could not be determined), the `build_date` and the `families` compiled
+/// in.
+struct BuildInfo;
+
+impl TableFunction for BuildInfo {
+    const COLUMNS: &'static [&'static str] = &["key text", "value text"];
+    const ARGUMENTS: &'static [&'static str] = &[];
+    const REQUIRED: usize = 0;
+
+    fn rows(&self, _args: &[Value]) -> Result<Rows> {
+        let families: Vec<String> = Families::available()
+            .iter_names()
+            .map(|(name, _)| name.to_lowercase())
+            .collect();
+        let rows: Vec<Result<Vec<Value>>> = [
+            ("version", env!("CARGO_PKG_VERSION").to_string()),
+            ("git_sha", env!("SURVEILR_GIT_SHA").to_string()),
+            ("sqlean_sha", env!("SURVEILR_SQLEAN_SHA").to_string()),
+            ("build_date", env!("SURVEILR_BUILD_DATE").to_string()),
+            ("families", families.join(",")),
+        ]
+        .into_iter()
+        .map(|(key, value)| Ok(vec![Value::Text(key.to_string()), Value::Text(value)]))
+        .collect();
+        Ok(Box::new(rows.into_iter()))
+    }
+}
+
+#[repr(C)]
+struct FunctionsTable {
+    base: ffi::sqlite3_vtab,
+    entries: Vec<CatalogEntry>,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for FunctionsTable {
+    type Aux = Vec<CatalogEntry>;
+    type Cursor = FunctionsCursor;
+
+    fn connect(
+        _db: &mut vtab::VTabConnection,
+        aux: Option<&Self::Aux>,
+        _args: &[&[u8]],
+    ) -> Result<(String, Self)> {
+        let schema = "CREATE TABLE x(name text, kind text, family text, narg integer, deterministic integer, direct_only integer, source text, version text)";
+        Ok((
+            schema.to_string(),
+            FunctionsTable {
+                base: ffi::sqlite3_vtab::default(),
+                entries: aux.cloned().unwrap_or_default(),
+            },
+        ))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
+        info.set_estimated_cost(self.entries.len() as f64);
+        Ok(())
+    }
+
+    fn open(&mut self) -> Result<Self::Cursor> {
+        Ok(FunctionsCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            entries: self.entries.clone(),
+            index: 0,
+        })
+    }
+}
+
+impl CreateVTab<'_> for FunctionsTable {
+    const KIND: VTabKind = VTabKind::EponymousOnly;
+}
+
+#[repr(C)]
+struct FunctionsCursor {
+    base: ffi::sqlite3_vtab_cursor,
+    entries: Vec<CatalogEntry>,
+    index: usize,
+}
+
+unsafe impl VTabCursor for FunctionsCursor {
+    fn filter(
+        &mut self,
+        _idx_num: c_int,
+        _idx_str: Option<&str>,
+        _args: &vtab::Values<'_>,
+    ) -> Result<()> {
+        self.index = 0;
+        Ok(())
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.index += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.index >= self.entries.len()
+    }
+
+    fn column(&self, ctx: &mut vtab::Context, col: i32) -> Result<()> {
+        let entry = &self.entries[self.index];
+        match col {
+            0 => ctx.set_result(&entry.name),
+            1 => ctx.set_result(&entry.kind),
+            2 => ctx.set_result(&entry.family),
+            3 => ctx.set_result(&entry.narg),
+            4 => ctx.set_result(&entry.deterministic),
+            5 => ctx.set_result(&entry.direct_only),
+            6 => ctx.set_result(&entry.source),
+            7 => ctx.set_result(&entry.version),
+            _ => Ok(()),
+        }
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.index as i64 + 1)
+    }
+}
+
+#[cfg(all(test, feature = "url"))]
+mod tests {
+    use crate::{register_all, Families};
+    use rusqlite::{Connection, Result};
+
+    #[test]
+    fn test_surveilr_functions() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        register_all(&conn, Families::URL)?;
+
+        let (kind, family, narg, deterministic, direct_only): (String, String, i64, bool, bool) =
+            conn.query_row(
+                "SELECT kind, family, narg, deterministic, direct_only FROM surveilr_functions WHERE name = 'url_host'",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)),
+            )?;
+        assert_eq!(
+            (
+                kind.as_str(),
+                family.as_str(),
+                narg,
+                deterministic,
+                direct_only
+            ),
+            ("scalar", "url", 1, true, false)
+        );
+
+        let (kind, narg): (String, Option<i64>) = conn.query_row(
+            "SELECT kind, narg FROM surveilr_functions WHERE name = 'url_query_each'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!((kind.as_str(), narg), ("module", None));
+
+        let variadic: i64 = conn.query_row(
+            "SELECT narg FROM surveilr_functions WHERE name = 'url'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(variadic, -1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_surveilr_build_info() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        register_all(&conn, Families::URL)?;
+
+        let info = |key: &str| -> Result<String> {
+            conn.query_row(
+                "SELECT value FROM surveilr_build_info WHERE key = ?",
+                [key],
+                |row| row.get(0),
+            )
+        };
+        assert_eq!(info("version")?, env!("CARGO_PKG_VERSION"));
+        assert_eq!(info("git_sha")?, env!("SURVEILR_GIT_SHA"));
+        assert_eq!(info("sqlean_sha")?, env!("SURVEILR_SQLEAN_SHA"));
+        assert_eq!(info("build_date")?, env!("SURVEILR_BUILD_DATE"));
+        assert!(info("families")?.split(',').any(|family| family == "url"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_surveilr_functions_accumulates() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        register_all(&conn, Families::URL)?;
+        register_all(&conn, Families::empty())?;
+        register_all(&conn, Families::URL)?;
+
+        let count: i64 = conn.query_row(
+            "SELECT count(*) FROM surveilr_functions WHERE name = 'url_host'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 1);
+        Ok(())
+    }
+}
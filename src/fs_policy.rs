@@ -0,0 +1,286 @@
+// the checks are only called by the fileio and lines_read replacements
+#![cfg_attr(not(feature = "sqlean-fileio"), allow(dead_code))]
+
+use rusqlite::{Connection, Error, Result};
+use std::io;
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+
+/// What the filesystem-touching functions (`fileio_*`, `readfile`, `writefile`, `lsdir`,
+/// `lines_read`) registered on a connection may do.
+///
+/// Paths are resolved (symlinks included) before they are checked against the allowed roots, so
+/// `../` tricks and links pointing outside a root are rejected.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FsPolicy {
+    /// `None` allows any path the process can reach.
+    roots: Option<Vec<PathBuf>>,
+    writable: bool,
+    max_file_size: Option<u64>,
+}
+
+impl Default for FsPolicy {
+    /// Same as [`FsPolicy::new`].
+    fn default() -> Self {
+        FsPolicy::new()
+    }
+}
+
+impl FsPolicy {
+    /// Read-only policy that allows no path until roots are added with [`FsPolicy::allow_root`].
+    pub fn new() -> FsPolicy {
+        FsPolicy {
+            roots: Some(vec![]),
+            writable: false,
+            max_file_size: None,
+        }
+    }
+
+    /// Read-write access to every path the process can reach, the behaviour of plain sqlean.
+    pub fn unrestricted() -> FsPolicy {
+        FsPolicy {
+            roots: None,
+            writable: true,
+            max_file_size: None,
+        }
+    }
+
+    /// Allows paths under `root` (which must exist when the functions are called).
+    pub fn allow_root(mut self, root: impl Into<PathBuf>) -> FsPolicy {
+        self.roots.get_or_insert_with(Vec::new).push(root.into());
+        self
+    }
+
+    /// Allows writing functions (`fileio_write`, `fileio_append`, `fileio_mkdir`, ...).
+    pub fn read_write(mut self) -> FsPolicy {
+        self.writable = true;
+        self
+    }
+
+    /// Rejects reading files larger than `bytes` and writing more than `bytes` to a file.
+    pub fn max_file_size(mut self, bytes: u64) -> FsPolicy {
+        self.max_file_size = Some(bytes);
+        self
+    }
+
+    pub(crate) fn is_restricted(&self) -> bool {
+        self.roots.is_some()
+    }
+
+    /// Resolves `path` for reading, failing if it falls outside the allowed roots. The returned
+    /// path may not exist.
+    pub(crate) fn check_read(&self, path: &str) -> Result<PathBuf> {
+        self.check_path(path)
+    }
+
+    /// Resolves `path` for writing, failing if it falls outside the allowed roots or the policy
+    /// is read-only.
+    pub(crate) fn check_write(&self, path: &str) -> Result<PathBuf> {
+        if !self.writable {
+            return Err(violation(format!(
+                "filesystem policy is read-only, cannot write to {}",
+                path
+            )));
+        }
+        self.check_path(path)
+    }
+
+    /// Fails if `size` bytes is more than the policy lets a single file hold.
+    pub(crate) fn check_size(&self, path: &str, size: u64) -> Result<()> {
+        match self.max_file_size {
+            Some(max) if size > max => Err(violation(format!(
+                "{} exceeds the filesystem policy's {} byte limit",
+                path, max
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn check_path(&self, path: &str) -> Result<PathBuf> {
+        let resolved = resolve(Path::new(path))
+            .map_err(|err| violation(format!("cannot resolve {}: {}", path, err)))?;
+        let Some(roots) = &self.roots else {
+            return Ok(resolved);
+        };
+
+        let allowed = roots
+            .iter()
+            .filter_map(|root| root.canonicalize().ok())
+            .any(|root| resolved.starts_with(root));
+        if allowed {
+            Ok(resolved)
+        } else {
+            Err(violation(format!(
+                "{} is outside the directories allowed by the filesystem policy",
+                path
+            )))
+        }
+    }
+}
+
+/// Canonicalizes `path`, tolerating a missing tail (a file about to be created) as long as it
+/// doesn't try to climb back out with `..`.
+fn resolve(path: &Path) -> io::Result<PathBuf> {
+    let absolute = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        std::env::current_dir()?.join(path)
+    };
+
+    let mut existing = absolute.as_path();
+    let mut missing = vec![];
+    loop {
+        match existing.canonicalize() {
+            Ok(resolved) => {
+                return Ok(missing
+                    .into_iter()
+                    .rev()
+                    .fold(resolved, |path, part| path.join(part)));
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let (Some(parent), Some(Component::Normal(part))) =
+                    (existing.parent(), existing.components().next_back())
+                else {
+                    return Err(err);
+                };
+                missing.push(part.to_os_string());
+                existing = parent;
+            }
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct PolicyViolation(String);
+
+impl std::fmt::Display for PolicyViolation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for PolicyViolation {}
+
+fn violation(message: String) -> Error {
+    Error::UserFunctionError(Box::new(PolicyViolation(message)))
+}
+
+fn is_registered(conn: &Connection, table: &str, name: &str) -> Result<bool> {
+    conn.query_row(
+        &format!("SELECT count(*) > 0 FROM {} WHERE name = ?", table),
+        [name],
+        |row| row.get(0),
+    )
+}
+
+/// Puts `conn`'s filesystem-touching functions under `policy`.
+///
+/// Only functions already registered on `conn` (by [`register_all`](crate::register_all) or the
+/// `initialize_*` auto extensions) are replaced; they are all flagged direct-only, so views and
+/// triggers can't call them. With a restricted policy the sqlean `vsv` module, which opens
+/// arbitrary files from `CREATE VIRTUAL TABLE`, is dropped.
+pub fn apply_fs_policy(conn: &Connection, policy: FsPolicy) -> Result<()> {
+    let policy = Arc::new(policy);
+
+    #[cfg(feature = "sqlean-fileio")]
+    if is_registered(conn, "pragma_function_list", "fileio_read")? {
+        crate::sqlean_extensions::register_fileio_functions(conn, policy.clone())?;
+    }
+    #[cfg(feature = "lines")]
+    if is_registered(conn, "pragma_module_list", "lines_read")? {
+        crate::sqlite_lines::register_lines_read(conn, policy.clone())?;
+    }
+
+    if policy.is_restricted() && is_registered(conn, "pragma_module_list", "vsv")? {
+        drop_module(conn, "vsv")?;
+    }
+    Ok(())
+}
+
+fn drop_module(conn: &Connection, name: &str) -> Result<()> {
+    let mut stmt = conn.prepare("SELECT name FROM pragma_module_list WHERE name <> ?")?;
+    let keep = stmt
+        .query_map([name], |row| row.get::<_, String>(0))?
+        .map(|name| {
+            name.map(|name| std::ffi::CString::new(name).expect("module names have no NUL"))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut keep_ptrs: Vec<*const std::os::raw::c_char> =
+        keep.iter().map(|name| name.as_ptr()).collect();
+    keep_ptrs.push(std::ptr::null());
+    let rc = unsafe { rusqlite::ffi::sqlite3_drop_modules(conn.handle(), keep_ptrs.as_mut_ptr()) };
+    if rc == rusqlite::ffi::SQLITE_OK {
+        Ok(())
+    } else {
+        Err(Error::SqliteFailure(rusqlite::ffi::Error::new(rc), None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "surveilr-fs-policy-{}-{}",
+            name,
+            std::process::id()
+        ));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir.canonicalize().unwrap()
+    }
+
+    #[test]
+    fn test_check_read_within_root() {
+        let root = temp_dir("read");
+        std::fs::write(root.join("a.txt"), "a").unwrap();
+        let policy = FsPolicy::new().allow_root(&root);
+
+        let path = root.join("a.txt");
+        assert_eq!(policy.check_read(path.to_str().unwrap()).unwrap(), path);
+        let missing = root.join("missing.txt");
+        assert_eq!(
+            policy.check_read(missing.to_str().unwrap()).unwrap(),
+            missing
+        );
+
+        assert!(policy.check_read("/etc/shadow").is_err());
+        let escape = root.join("../../etc/passwd");
+        assert!(policy.check_read(escape.to_str().unwrap()).is_err());
+        let escape = root.join("nope/../../etc/passwd");
+        assert!(policy.check_read(escape.to_str().unwrap()).is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_check_read_symlink_escape() {
+        let root = temp_dir("symlink");
+        let link = root.join("shadow");
+        let _ = std::fs::remove_file(&link);
+        std::os::unix::fs::symlink("/etc/passwd", &link).unwrap();
+        let policy = FsPolicy::new().allow_root(&root);
+
+        assert!(policy.check_read(link.to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_check_write_and_size() {
+        let root = temp_dir("write");
+        let path = root.join("out.txt");
+        let path = path.to_str().unwrap();
+
+        assert!(FsPolicy::new().allow_root(&root).check_write(path).is_err());
+        assert!(FsPolicy::new()
+            .allow_root(&root)
+            .read_write()
+            .check_write(path)
+            .is_ok());
+
+        let policy = FsPolicy::unrestricted().max_file_size(4);
+        assert!(policy.check_size(path, 4).is_ok());
+        assert!(policy.check_size(path, 5).is_err());
+        assert!(FsPolicy::new().check_read(path).is_err());
+    }
+}
@@ -0,0 +1,540 @@
+//! Instrumentation of the functions the C extensions register.
+//!
+//! The sqlean sources are built with their `sqlite3_create_function*`, `sqlite3_user_data` and
+//! `sqlite3_result_*` calls renamed to the `surveilr_*` functions below (see build.rs); in
+//! loadable mode the same functions are patched into the routines handed to their entrypoints.
+//! Functions created on a connection with statistics enabled are registered with a [`Shim`] as
+//! their user data and trampolines that time the original callbacks. `surveilr_sqlite3_user_data`
+//! hands the original user data back to them, and the `result_*` calls feed the output bytes and
+//! errors of the call in progress.
+
+use rusqlite::ffi::{self, sqlite3, sqlite3_context, sqlite3_value};
+use std::cell::Cell;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uchar, c_void};
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::{registry_for, FunctionStats};
+
+type XFunc = unsafe extern "C" fn(*mut sqlite3_context, c_int, *mut *mut sqlite3_value);
+type XFinal = unsafe extern "C" fn(*mut sqlite3_context);
+type XDestroy = unsafe extern "C" fn(*mut c_void);
+
+/// User data of an instrumented function: the original one and its callbacks.
+struct Shim {
+    app: *mut c_void,
+    x_func: Option<XFunc>,
+    x_step: Option<XFunc>,
+    x_final: Option<XFinal>,
+    x_value: Option<XFinal>,
+    x_inverse: Option<XFunc>,
+    x_destroy: Option<XDestroy>,
+    stats: Arc<FunctionStats>,
+}
+
+/// The instrumented callback running on this thread.
+#[derive(Clone, Copy)]
+struct Active {
+    ctx: *mut sqlite3_context,
+    shim: *const Shim,
+    bytes_out: u64,
+    error: bool,
+}
+
+thread_local! {
+    static ACTIVE: Cell<Option<Active>> = const { Cell::new(None) };
+}
+
+fn update_active(ctx: *mut sqlite3_context, update: impl FnOnce(&mut Active)) {
+    ACTIVE.with(|active| {
+        if let Some(mut current) = active.get().filter(|current| current.ctx == ctx) {
+            update(&mut current);
+            active.set(Some(current));
+        }
+    });
+}
+
+unsafe fn args_bytes(argc: c_int, argv: *mut *mut sqlite3_value) -> u64 {
+    (0..argc.max(0) as usize)
+        .map(|i| *argv.add(i))
+        .filter(|value| {
+            matches!(
+                ffi::sqlite3_value_type(*value),
+                ffi::SQLITE_TEXT | ffi::SQLITE_BLOB
+            )
+        })
+        .map(|value| ffi::sqlite3_value_bytes(value).max(0) as u64)
+        .sum()
+}
+
+/// Runs one original callback with `ctx` marked as active, then records it.
+unsafe fn instrumented(
+    ctx: *mut sqlite3_context,
+    call: bool,
+    bytes_in: u64,
+    callback: impl FnOnce(),
+) {
+    let shim = ffi::sqlite3_user_data(ctx) as *const Shim;
+    let previous = ACTIVE.with(|active| {
+        active.replace(Some(Active {
+            ctx,
+            shim,
+            bytes_out: 0,
+            error: false,
+        }))
+    });
+    let start = Instant::now();
+    callback();
+    let elapsed = start.elapsed();
+    let current = ACTIVE.with(|active| active.replace(previous));
+
+    if let Some(current) = current {
+        (*shim)
+            .stats
+            .record(call, elapsed, bytes_in, current.bytes_out, current.error);
+    }
+}
+
+unsafe extern "C" fn shim_func(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    let shim = &*(ffi::sqlite3_user_data(ctx) as *const Shim);
+    if let Some(x_func) = shim.x_func {
+        instrumented(ctx, true, args_bytes(argc, argv), || {
+            x_func(ctx, argc, argv)
+        });
+    }
+}
+
+unsafe extern "C" fn shim_step(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    let shim = &*(ffi::sqlite3_user_data(ctx) as *const Shim);
+    if let Some(x_step) = shim.x_step {
+        instrumented(ctx, true, args_bytes(argc, argv), || {
+            x_step(ctx, argc, argv)
+        });
+    }
+}
+
+unsafe extern "C" fn shim_inverse(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    let shim = &*(ffi::sqlite3_user_data(ctx) as *const Shim);
+    if let Some(x_inverse) = shim.x_inverse {
+        instrumented(ctx, false, args_bytes(argc, argv), || {
+            x_inverse(ctx, argc, argv)
+        });
+    }
+}
+
+unsafe extern "C" fn shim_final(ctx: *mut sqlite3_context) {
+    let shim = &*(ffi::sqlite3_user_data(ctx) as *const Shim);
+    if let Some(x_final) = shim.x_final {
+        instrumented(ctx, false, 0, || x_final(ctx));
+    }
+}
+
+unsafe extern "C" fn shim_value(ctx: *mut sqlite3_context) {
+    let shim = &*(ffi::sqlite3_user_data(ctx) as *const Shim);
+    if let Some(x_value) = shim.x_value {
+        instrumented(ctx, false, 0, || x_value(ctx));
+    }
+}
+
+unsafe extern "C" fn shim_destroy(shim: *mut c_void) {
+    let shim = Box::from_raw(shim as *mut Shim);
+    if let Some(x_destroy) = shim.x_destroy {
+        x_destroy(shim.app);
+    }
+}
+
+/// `sqlite3_create_function_v2`, instrumenting the function when `db` has statistics enabled.
+///
+/// # Safety
+///
+/// Same contract as `sqlite3_create_function_v2`.
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_create_function_v2(
+    db: *mut sqlite3,
+    name: *const c_char,
+    n_arg: c_int,
+    text_rep: c_int,
+    app: *mut c_void,
+    x_func: Option<XFunc>,
+    x_step: Option<XFunc>,
+    x_final: Option<XFinal>,
+    x_destroy: Option<XDestroy>,
+) -> c_int {
+    create_function(
+        db, name, n_arg, text_rep, app, x_func, x_step, x_final, None, None, x_destroy,
+    )
+}
+
+/// `sqlite3_create_function`, see [`surveilr_sqlite3_create_function_v2`].
+///
+/// # Safety
+///
+/// Same contract as `sqlite3_create_function`.
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_create_function(
+    db: *mut sqlite3,
+    name: *const c_char,
+    n_arg: c_int,
+    text_rep: c_int,
+    app: *mut c_void,
+    x_func: Option<XFunc>,
+    x_step: Option<XFunc>,
+    x_final: Option<XFinal>,
+) -> c_int {
+    surveilr_sqlite3_create_function_v2(
+        db, name, n_arg, text_rep, app, x_func, x_step, x_final, None,
+    )
+}
+
+/// `sqlite3_create_window_function`, see [`surveilr_sqlite3_create_function_v2`].
+///
+/// # Safety
+///
+/// Same contract as `sqlite3_create_window_function`.
+#[allow(clippy::too_many_arguments)]
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_create_window_function(
+    db: *mut sqlite3,
+    name: *const c_char,
+    n_arg: c_int,
+    text_rep: c_int,
+    app: *mut c_void,
+    x_step: Option<XFunc>,
+    x_final: Option<XFinal>,
+    x_value: Option<XFinal>,
+    x_inverse: Option<XFunc>,
+    x_destroy: Option<XDestroy>,
+) -> c_int {
+    create_function(
+        db, name, n_arg, text_rep, app, None, x_step, x_final, x_value, x_inverse, x_destroy,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+unsafe fn create_function(
+    db: *mut sqlite3,
+    name: *const c_char,
+    n_arg: c_int,
+    text_rep: c_int,
+    app: *mut c_void,
+    x_func: Option<XFunc>,
+    x_step: Option<XFunc>,
+    x_final: Option<XFinal>,
+    x_value: Option<XFinal>,
+    x_inverse: Option<XFunc>,
+    x_destroy: Option<XDestroy>,
+) -> c_int {
+    let deleting = x_func.is_none() && x_step.is_none();
+    let registry = (!deleting && !name.is_null())
+        .then(|| registry_for(db))
+        .flatten();
+    let Some(registry) = registry else {
+        return if x_value.is_some() || x_inverse.is_some() {
+            ffi::sqlite3_create_window_function(
+                db, name, n_arg, text_rep, app, x_step, x_final, x_value, x_inverse, x_destroy,
+            )
+        } else {
+            ffi::sqlite3_create_function_v2(
+                db, name, n_arg, text_rep, app, x_func, x_step, x_final, x_destroy,
+            )
+        };
+    };
+
+    let function_name = CStr::from_ptr(name).to_string_lossy();
+    let shim = Box::into_raw(Box::new(Shim {
+        app,
+        x_func,
+        x_step,
+        x_final,
+        x_value,
+        x_inverse,
+        x_destroy,
+        stats: registry.function(&function_name, n_arg),
+    }));
+    // SQLite calls shim_destroy even when the registration fails
+    if x_value.is_some() || x_inverse.is_some() {
+        ffi::sqlite3_create_window_function(
+            db,
+            name,
+            n_arg,
+            text_rep,
+            shim.cast(),
+            x_step.and(Some(shim_step as XFunc)),
+            x_final.and(Some(shim_final as XFinal)),
+            x_value.and(Some(shim_value as XFinal)),
+            x_inverse.and(Some(shim_inverse as XFunc)),
+            Some(shim_destroy),
+        )
+    } else {
+        ffi::sqlite3_create_function_v2(
+            db,
+            name,
+            n_arg,
+            text_rep,
+            shim.cast(),
+            x_func.and(Some(shim_func as XFunc)),
+            x_step.and(Some(shim_step as XFunc)),
+            x_final.and(Some(shim_final as XFinal)),
+            Some(shim_destroy),
+        )
+    }
+}
+
+/// `sqlite3_user_data`, returning the original user data to instrumented callbacks.
+///
+/// # Safety
+///
+/// Same contract as `sqlite3_user_data`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_user_data(ctx: *mut sqlite3_context) -> *mut c_void {
+    let active = ACTIVE.with(|active| active.get().filter(|active| active.ctx == ctx));
+    match active {
+        Some(active) => (*active.shim).app,
+        None => ffi::sqlite3_user_data(ctx),
+    }
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_text`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_text(
+    ctx: *mut sqlite3_context,
+    text: *const c_char,
+    n: c_int,
+    destructor: Option<XDestroy>,
+) {
+    let bytes = match n {
+        _ if text.is_null() => 0,
+        n if n < 0 => CStr::from_ptr(text).to_bytes().len() as u64,
+        n => n as u64,
+    };
+    update_active(ctx, |active| active.bytes_out += bytes);
+    ffi::sqlite3_result_text(ctx, text, n, destructor)
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_text64`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_text64(
+    ctx: *mut sqlite3_context,
+    text: *const c_char,
+    n: ffi::sqlite3_uint64,
+    destructor: Option<XDestroy>,
+    encoding: c_uchar,
+) {
+    update_active(ctx, |active| active.bytes_out += n);
+    ffi::sqlite3_result_text64(ctx, text, n, destructor, encoding)
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_blob`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_blob(
+    ctx: *mut sqlite3_context,
+    blob: *const c_void,
+    n: c_int,
+    destructor: Option<XDestroy>,
+) {
+    update_active(ctx, |active| active.bytes_out += n.max(0) as u64);
+    ffi::sqlite3_result_blob(ctx, blob, n, destructor)
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_blob64`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_blob64(
+    ctx: *mut sqlite3_context,
+    blob: *const c_void,
+    n: ffi::sqlite3_uint64,
+    destructor: Option<XDestroy>,
+) {
+    update_active(ctx, |active| active.bytes_out += n);
+    ffi::sqlite3_result_blob64(ctx, blob, n, destructor)
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_value`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_value(
+    ctx: *mut sqlite3_context,
+    value: *mut sqlite3_value,
+) {
+    let bytes = args_bytes(1, &value as *const _ as *mut _);
+    update_active(ctx, |active| active.bytes_out += bytes);
+    ffi::sqlite3_result_value(ctx, value)
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_error`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_error(
+    ctx: *mut sqlite3_context,
+    message: *const c_char,
+    n: c_int,
+) {
+    update_active(ctx, |active| active.error = true);
+    ffi::sqlite3_result_error(ctx, message, n)
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_error_code`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_error_code(
+    ctx: *mut sqlite3_context,
+    code: c_int,
+) {
+    update_active(ctx, |active| active.error = true);
+    ffi::sqlite3_result_error_code(ctx, code)
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_error_nomem`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_error_nomem(ctx: *mut sqlite3_context) {
+    update_active(ctx, |active| active.error = true);
+    ffi::sqlite3_result_error_nomem(ctx)
+}
+
+/// # Safety
+///
+/// Same contract as `sqlite3_result_error_toobig`.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_sqlite3_result_error_toobig(ctx: *mut sqlite3_context) {
+    update_active(ctx, |active| active.error = true);
+    ffi::sqlite3_result_error_toobig(ctx)
+}
+
+/// Copy of the host's routines with the functions above patched in, handed to the C extension
+/// entrypoints in loadable mode. Built once, from the routines of the first load.
+#[cfg(feature = "loadable_extension")]
+pub(crate) fn instrumented_api_routines(
+    api: *const ffi::sqlite3_api_routines,
+) -> *const ffi::sqlite3_api_routines {
+    use std::ptr;
+    use std::sync::OnceLock;
+
+    static ROUTINES: OnceLock<usize> = OnceLock::new();
+    if api.is_null() {
+        return ptr::null();
+    }
+    *ROUTINES.get_or_init(|| {
+        let mut routines = unsafe { ptr::read(api) };
+        routines.create_function = Some(surveilr_sqlite3_create_function);
+        routines.create_function_v2 = Some(surveilr_sqlite3_create_function_v2);
+        routines.create_window_function = Some(surveilr_sqlite3_create_window_function);
+        routines.user_data = Some(surveilr_sqlite3_user_data);
+        routines.result_text = Some(surveilr_sqlite3_result_text);
+        routines.result_text64 = Some(surveilr_sqlite3_result_text64);
+        routines.result_blob = Some(surveilr_sqlite3_result_blob);
+        routines.result_blob64 = Some(surveilr_sqlite3_result_blob64);
+        routines.result_value = Some(surveilr_sqlite3_result_value);
+        routines.result_error = Some(surveilr_sqlite3_result_error);
+        routines.result_error_code = Some(surveilr_sqlite3_result_error_code);
+        routines.result_error_nomem = Some(surveilr_sqlite3_result_error_nomem);
+        routines.result_error_toobig = Some(surveilr_sqlite3_result_error_toobig);
+        Box::into_raw(Box::new(routines)) as usize
+    }) as *const ffi::sqlite3_api_routines
+}
+
+#[cfg(all(test, not(feature = "loadable_extension")))]
+mod tests {
+    use super::*;
+    use rusqlite::{Connection, Result};
+
+    /// A C-style function: reads its user data and reports through `sqlite3_result_*`, as the
+    /// renamed sqlean sources do.
+    unsafe extern "C" fn c_repeat(
+        ctx: *mut sqlite3_context,
+        _argc: c_int,
+        argv: *mut *mut sqlite3_value,
+    ) {
+        let times = *(surveilr_sqlite3_user_data(ctx) as *const usize);
+        let text = ffi::sqlite3_value_text(*argv);
+        if text.is_null() {
+            let message = c"null input";
+            surveilr_sqlite3_result_error(ctx, message.as_ptr(), -1);
+            return;
+        }
+        let text = CStr::from_ptr(text.cast()).to_bytes().repeat(times);
+        surveilr_sqlite3_result_text(
+            ctx,
+            text.as_ptr().cast(),
+            text.len() as c_int,
+            ffi::SQLITE_TRANSIENT(),
+        );
+    }
+
+    unsafe extern "C" fn free_times(times: *mut c_void) {
+        drop(Box::from_raw(times as *mut usize));
+    }
+
+    fn register_c_repeat(conn: &Connection) -> c_int {
+        let times = Box::into_raw(Box::new(3usize));
+        unsafe {
+            surveilr_sqlite3_create_function_v2(
+                conn.handle(),
+                c"c_repeat".as_ptr(),
+                1,
+                ffi::SQLITE_UTF8,
+                times.cast(),
+                Some(c_repeat),
+                None,
+                None,
+                Some(free_times),
+            )
+        }
+    }
+
+    #[test]
+    fn test_interposed_function() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        crate::enable_function_stats(&conn)?;
+        assert_eq!(register_c_repeat(&conn), ffi::SQLITE_OK);
+
+        let repeated: String = conn.query_row("SELECT c_repeat('ab')", [], |row| row.get(0))?;
+        assert_eq!(repeated, "ababab");
+        assert!(conn
+            .query_row("SELECT c_repeat(NULL)", [], |_| Ok(()))
+            .is_err());
+
+        let stats: (i64, i64, i64, i64) = conn.query_row(
+            "SELECT calls, errors, bytes_in, bytes_out FROM surveilr_function_stats WHERE name = 'c_repeat'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )?;
+        assert_eq!(stats, (2, 1, 2, 6));
+        Ok(())
+    }
+
+    #[test]
+    fn test_interposed_function_without_stats() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        assert_eq!(register_c_repeat(&conn), ffi::SQLITE_OK);
+        let repeated: String = conn.query_row("SELECT c_repeat('x')", [], |row| row.get(0))?;
+        assert_eq!(repeated, "xxx");
+        Ok(())
+    }
+}
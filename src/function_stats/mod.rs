@@ -0,0 +1,532 @@
+//! Opt-in per-function execution statistics, see [`enable_function_stats`].
+
+// the wrappers are only called by the Rust function families
+#![cfg_attr(
+    not(any(feature = "url", feature = "lines", feature = "sqlean-fileio")),
+    allow(dead_code)
+)]
+
+use rusqlite::{
+    functions::{Aggregate, Context, FunctionFlags, SqlFnOutput},
+    types::{ToSqlOutput, Value, ValueRef},
+    Connection, Result,
+};
+use std::os::raw::c_int;
+use std::panic::{RefUnwindSafe, UnwindSafe};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+
+use crate::{register_table_function, Rows, TableFunction};
+
+#[cfg(sqlean)]
+pub(crate) mod interpose;
+
+/// Counters for one function overload.
+#[derive(Debug, Default)]
+pub(crate) struct FunctionStats {
+    name: String,
+    narg: c_int,
+    calls: AtomicU64,
+    errors: AtomicU64,
+    total_ns: AtomicU64,
+    max_ns: AtomicU64,
+    bytes_in: AtomicU64,
+    bytes_out: AtomicU64,
+}
+
+impl FunctionStats {
+    /// Records one callback. `call` is false for the callbacks of an aggregate that don't start a
+    /// new step (`xFinal`, `xValue`, `xInverse`), which only add to the timings.
+    pub(crate) fn record(
+        &self,
+        call: bool,
+        elapsed: Duration,
+        bytes_in: u64,
+        bytes_out: u64,
+        error: bool,
+    ) {
+        let elapsed = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        if call {
+            self.calls.fetch_add(1, Ordering::Relaxed);
+        }
+        if error {
+            self.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        self.total_ns.fetch_add(elapsed, Ordering::Relaxed);
+        self.max_ns.fetch_max(elapsed, Ordering::Relaxed);
+        self.bytes_in.fetch_add(bytes_in, Ordering::Relaxed);
+        self.bytes_out.fetch_add(bytes_out, Ordering::Relaxed);
+    }
+
+    fn reset(&self) {
+        for counter in [
+            &self.calls,
+            &self.errors,
+            &self.total_ns,
+            &self.max_ns,
+            &self.bytes_in,
+            &self.bytes_out,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+
+    fn row(&self) -> Vec<Value> {
+        let counter = |counter: &AtomicU64| {
+            Value::Integer(i64::try_from(counter.load(Ordering::Relaxed)).unwrap_or(i64::MAX))
+        };
+        vec![
+            Value::Text(self.name.clone()),
+            Value::Integer(self.narg.into()),
+            counter(&self.calls),
+            counter(&self.errors),
+            counter(&self.total_ns),
+            counter(&self.max_ns),
+            counter(&self.bytes_in),
+            counter(&self.bytes_out),
+        ]
+    }
+}
+
+/// Statistics of every instrumented function on one connection.
+#[derive(Debug, Default)]
+pub(crate) struct StatsRegistry {
+    functions: Mutex<Vec<Arc<FunctionStats>>>,
+}
+
+impl StatsRegistry {
+    /// Counters for `name`/`narg`, shared by every registration of that overload.
+    pub(crate) fn function(&self, name: &str, narg: c_int) -> Arc<FunctionStats> {
+        let mut functions = self.functions.lock().unwrap();
+        if let Some(stats) = functions
+            .iter()
+            .find(|stats| stats.name.eq_ignore_ascii_case(name) && stats.narg == narg)
+        {
+            return stats.clone();
+        }
+        let stats = Arc::new(FunctionStats {
+            name: name.to_string(),
+            narg,
+            ..FunctionStats::default()
+        });
+        functions.push(stats.clone());
+        stats
+    }
+
+    fn reset(&self, name: Option<&str>) -> usize {
+        let functions = self.functions.lock().unwrap();
+        functions
+            .iter()
+            .filter(|stats| name.is_none_or(|name| stats.name.eq_ignore_ascii_case(name)))
+            .inspect(|stats| stats.reset())
+            .count()
+    }
+}
+
+/// Registries by connection handle. They are owned by the connection's `surveilr_function_stats`
+/// module, so a closed connection leaves a dead entry behind instead of leaking its counters.
+static REGISTRIES: Mutex<Vec<(usize, Weak<StatsRegistry>)>> = Mutex::new(Vec::new());
+
+/// The registry of the connection behind `db`, if [`enable_function_stats`] was called on it.
+pub(crate) fn registry_for(db: *mut rusqlite::ffi::sqlite3) -> Option<Arc<StatsRegistry>> {
+    let registries = REGISTRIES.lock().unwrap();
+    registries
+        .iter()
+        .find(|(handle, _)| *handle == db as usize)
+        .and_then(|(_, registry)| registry.upgrade())
+}
+
+/// `surveilr_function_stats`: one row per instrumented function overload.
+struct StatsTable(Arc<StatsRegistry>);
+
+impl TableFunction for StatsTable {
+    const COLUMNS: &'static [&'static str] = &[
+        "name text",
+        "narg integer",
+        "calls integer",
+        "errors integer",
+        "total_ns integer",
+        "max_ns integer",
+        "bytes_in integer",
+        "bytes_out integer",
+    ];
+    const ARGUMENTS: &'static [&'static str] = &[];
+    const REQUIRED: usize = 0;
+
+    fn rows(&self, _args: &[Value]) -> Result<Rows> {
+        let rows: Vec<Result<Vec<Value>>> = self
+            .0
+            .functions
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|stats| Ok(stats.row()))
+            .collect();
+        Ok(Box::new(rows.into_iter()))
+    }
+}
+
+/// Turns on execution statistics for the functions registered on `conn` from now on, by
+/// [`register_all`](crate::register_all) and the `register_*` functions of this crate.
+///
+/// Every call is timed and counted, along with the errors it raised and the text and blob bytes
+/// it received and returned. The numbers are listed by the eponymous `surveilr_function_stats`
+/// table (`name`, `narg`, `calls`, `errors`, `total_ns`, `max_ns`, `bytes_in`, `bytes_out`) and
+/// zeroed by `surveilr_function_stats_reset()`, or `surveilr_function_stats_reset(name)` for a
+/// single function; both return how many overloads were reset. For aggregates every `xStep` is a
+/// call and the time spent in `xFinal` is added to it. Table-valued functions and the
+/// `fileio_*` and `lines*` modules count a call per scan, with the time spent producing its rows
+/// and their bytes.
+///
+/// Functions registered before this call, or as auto extensions by the `initialize_*` functions,
+/// are not instrumented. Calling it again keeps the existing counters.
+pub fn enable_function_stats(conn: &Connection) -> Result<()> {
+    if registry_for(unsafe { conn.handle() }).is_some() {
+        return Ok(());
+    }
+
+    let registry = Arc::new(StatsRegistry::default());
+    register_table_function(
+        conn,
+        "surveilr_function_stats",
+        StatsTable(registry.clone()),
+    )?;
+    for n_arg in [0, 1] {
+        let registry = registry.clone();
+        conn.create_scalar_function(
+            "surveilr_function_stats_reset",
+            n_arg,
+            FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DIRECTONLY,
+            move |ctx| {
+                let name = match ctx.len() {
+                    0 => None,
+                    _ => Some(ctx.get::<String>(0)?),
+                };
+                Ok(registry.reset(name.as_deref()) as i64)
+            },
+        )?;
+    }
+
+    let mut registries = REGISTRIES.lock().unwrap();
+    registries.retain(|(_, registry)| registry.strong_count() > 0);
+    registries.push((unsafe { conn.handle() } as usize, Arc::downgrade(&registry)));
+    Ok(())
+}
+
+pub(crate) fn value_bytes(value: ValueRef<'_>) -> u64 {
+    match value {
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => bytes.len() as u64,
+        _ => 0,
+    }
+}
+
+fn output_bytes(output: &impl SqlFnOutput) -> u64 {
+    match output.to_sql() {
+        Ok((ToSqlOutput::Borrowed(value), _)) => value_bytes(value),
+        Ok((ToSqlOutput::Owned(value), _)) => value_bytes(ValueRef::from(&value)),
+        _ => 0,
+    }
+}
+
+/// Counters for the table-valued function or virtual-table module `name` with `narg` hidden
+/// arguments, `None` unless statistics are enabled on `conn`. Cursors record every `xFilter` as
+/// a call and add the time spent in `xNext` to it, along with the bytes of the rows produced.
+pub(crate) fn module_stats(
+    conn: &Connection,
+    name: &str,
+    narg: usize,
+) -> Option<Arc<FunctionStats>> {
+    registry_for(unsafe { conn.handle() }).map(|registry| registry.function(name, narg as c_int))
+}
+
+/// Records a cursor callback that started at `start` in `stats`, see [`module_stats`].
+pub(crate) fn record_callback(
+    stats: &Option<Arc<FunctionStats>>,
+    call: bool,
+    start: Instant,
+    bytes_in: u64,
+    bytes_out: u64,
+    error: bool,
+) {
+    if let Some(stats) = stats {
+        stats.record(call, start.elapsed(), bytes_in, bytes_out, error);
+    }
+}
+
+/// Text and blob bytes of a row produced by a virtual table.
+pub(crate) fn row_bytes(row: &[Value]) -> u64 {
+    row.iter().map(|value| value_bytes(value.into())).sum()
+}
+
+/// [`Connection::create_scalar_function`], timed and counted when statistics are enabled on
+/// `conn`. Every Rust function of this crate is registered through it.
+pub(crate) fn create_scalar_function<F, T>(
+    conn: &Connection,
+    fn_name: &str,
+    n_arg: c_int,
+    flags: FunctionFlags,
+    mut x_func: F,
+) -> Result<()>
+where
+    F: FnMut(&Context<'_>) -> Result<T> + Send + 'static,
+    T: SqlFnOutput,
+{
+    let Some(registry) = registry_for(unsafe { conn.handle() }) else {
+        return conn.create_scalar_function(fn_name, n_arg, flags, x_func);
+    };
+
+    let stats = registry.function(fn_name, n_arg);
+    conn.create_scalar_function(fn_name, n_arg, flags, move |ctx| {
+        let bytes_in = (0..ctx.len()).map(|i| value_bytes(ctx.get_raw(i))).sum();
+        let start = Instant::now();
+        let result = x_func(ctx);
+        let elapsed = start.elapsed();
+        let bytes_out = result.as_ref().map_or(0, output_bytes);
+        stats.record(true, elapsed, bytes_in, bytes_out, result.is_err());
+        result
+    })
+}
+
+/// [`Connection::create_aggregate_function`], timed and counted when statistics are enabled on
+/// `conn`: every `xStep` is a call and the time spent in `xFinal` is added to them.
+#[cfg_attr(not(feature = "url"), allow(dead_code))]
+pub(crate) fn create_aggregate_function<A, D, T>(
+    conn: &Connection,
+    fn_name: &str,
+    n_arg: c_int,
+    flags: FunctionFlags,
+    aggr: D,
+) -> Result<()>
+where
+    A: RefUnwindSafe + UnwindSafe,
+    D: Aggregate<A, T> + 'static,
+    T: SqlFnOutput,
+{
+    let Some(registry) = registry_for(unsafe { conn.handle() }) else {
+        return conn.create_aggregate_function(fn_name, n_arg, flags, aggr);
+    };
+
+    let stats = registry.function(fn_name, n_arg);
+    conn.create_aggregate_function(fn_name, n_arg, flags, TimedAggregate { aggr, stats })
+}
+
+#[cfg_attr(not(feature = "url"), allow(dead_code))]
+struct TimedAggregate<D> {
+    aggr: D,
+    stats: Arc<FunctionStats>,
+}
+
+impl<A, D, T> Aggregate<A, T> for TimedAggregate<D>
+where
+    A: RefUnwindSafe + UnwindSafe,
+    D: Aggregate<A, T>,
+    T: SqlFnOutput,
+{
+    fn init(&self, ctx: &mut Context<'_>) -> Result<A> {
+        self.aggr.init(ctx)
+    }
+
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut A) -> Result<()> {
+        let bytes_in = (0..ctx.len()).map(|i| value_bytes(ctx.get_raw(i))).sum();
+        let start = Instant::now();
+        let result = self.aggr.step(ctx, acc);
+        self.stats
+            .record(true, start.elapsed(), bytes_in, 0, result.is_err());
+        result
+    }
+
+    fn finalize(&self, ctx: &mut Context<'_>, acc: Option<A>) -> Result<T> {
+        let start = Instant::now();
+        let result = self.aggr.finalize(ctx, acc);
+        let bytes_out = result.as_ref().map_or(0, output_bytes);
+        self.stats
+            .record(false, start.elapsed(), 0, bytes_out, result.is_err());
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Error;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        enable_function_stats(&conn).unwrap();
+        create_scalar_function(&conn, "stats_echo", 1, FunctionFlags::SQLITE_UTF8, |ctx| {
+            let text: String = ctx.get(0)?;
+            if text == "fail" {
+                return Err(Error::UserFunctionError("failed".into()));
+            }
+            Ok(format!("{}!", text))
+        })
+        .unwrap();
+        conn
+    }
+
+    fn stats(conn: &Connection, name: &str) -> (i64, i64, i64, i64) {
+        conn.query_row(
+            "SELECT calls, errors, bytes_in, bytes_out FROM surveilr_function_stats WHERE name = ?",
+            [name],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_function_stats() -> Result<()> {
+        let conn = setup_connection();
+        conn.query_row("SELECT stats_echo('abc'), stats_echo('de')", [], |_| Ok(()))?;
+        assert!(conn
+            .query_row("SELECT stats_echo('fail')", [], |_| Ok(()))
+            .is_err());
+
+        assert_eq!(stats(&conn, "stats_echo"), (3, 1, 9, 7));
+        let (total, max): (i64, i64) = conn.query_row(
+            "SELECT total_ns, max_ns FROM surveilr_function_stats WHERE name = 'stats_echo'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert!(max <= total);
+
+        let reset: i64 = conn.query_row("SELECT surveilr_function_stats_reset()", [], |row| {
+            row.get(0)
+        })?;
+        assert_eq!(reset, 1);
+        assert_eq!(stats(&conn, "stats_echo"), (0, 0, 0, 0));
+        Ok(())
+    }
+
+    struct Concat;
+
+    impl Aggregate<String, String> for Concat {
+        fn init(&self, _ctx: &mut Context<'_>) -> Result<String> {
+            Ok(String::new())
+        }
+
+        fn step(&self, ctx: &mut Context<'_>, acc: &mut String) -> Result<()> {
+            acc.push_str(&ctx.get::<String>(0)?);
+            Ok(())
+        }
+
+        fn finalize(&self, _ctx: &mut Context<'_>, acc: Option<String>) -> Result<String> {
+            Ok(acc.unwrap_or_default())
+        }
+    }
+
+    #[test]
+    fn test_aggregate_function_stats() -> Result<()> {
+        let conn = setup_connection();
+        create_aggregate_function(&conn, "stats_concat", 1, FunctionFlags::SQLITE_UTF8, Concat)?;
+        let concat: String = conn.query_row(
+            "SELECT stats_concat(column1) FROM (VALUES ('ab'), ('c'), ('de'))",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(concat, "abcde");
+        assert_eq!(stats(&conn, "stats_concat"), (3, 0, 5, 5));
+        Ok(())
+    }
+
+    #[test]
+    fn test_function_stats_are_opt_in() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        create_scalar_function(&conn, "plain", 0, FunctionFlags::SQLITE_UTF8, |_| Ok(1))?;
+        assert!(registry_for(unsafe { conn.handle() }).is_none());
+        assert!(conn
+            .query_row("SELECT count(*) FROM surveilr_function_stats", [], |_| Ok(
+                ()
+            ))
+            .is_err());
+
+        // registering before enabling leaves the function alone
+        enable_function_stats(&conn)?;
+        conn.query_row("SELECT plain()", [], |_| Ok(()))?;
+        let count: i64 =
+            conn.query_row("SELECT count(*) FROM surveilr_function_stats", [], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(count, 0);
+        Ok(())
+    }
+
+    struct Chars;
+
+    impl TableFunction for Chars {
+        const COLUMNS: &'static [&'static str] = &["value text"];
+        const ARGUMENTS: &'static [&'static str] = &["input"];
+
+        fn rows(&self, args: &[Value]) -> Result<Rows> {
+            let Value::Text(input) = &args[0] else {
+                return Err(Error::ModuleError("input must be text.".to_string()));
+            };
+            let rows: Vec<Result<Vec<Value>>> = input
+                .chars()
+                .map(|c| Ok(vec![Value::Text(c.to_string())]))
+                .collect();
+            Ok(Box::new(rows.into_iter()))
+        }
+    }
+
+    #[test]
+    fn test_table_function_stats() -> Result<()> {
+        let conn = setup_connection();
+        register_table_function(&conn, "stats_chars", Chars)?;
+        let count: i64 = conn.query_row(
+            "SELECT count(*) FROM stats_chars('abc'), stats_chars('de')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 6);
+        assert!(conn
+            .query_row("SELECT * FROM stats_chars(1)", [], |_| Ok(()))
+            .is_err());
+
+        // the inner scan runs once per outer row
+        assert_eq!(stats(&conn, "stats_chars"), (5, 1, 9, 9));
+        Ok(())
+    }
+
+    #[cfg(feature = "lines")]
+    #[test]
+    fn test_lines_module_stats() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        enable_function_stats(&conn)?;
+        crate::register_all(&conn, crate::Families::LINES)?;
+
+        let count: i64 =
+            conn.query_row("SELECT count(*) FROM lines('a\nbc')", [], |row| row.get(0))?;
+        assert_eq!(count, 2);
+        assert_eq!(stats(&conn, "lines"), (1, 0, 4, 3));
+
+        let path = "src/sqlite_lines/test_files/test.txt";
+        let count: i64 = conn.query_row("SELECT count(*) FROM lines_read(?)", [path], |row| {
+            row.get(0)
+        })?;
+        assert_eq!(count, 3);
+        assert!(conn
+            .query_row(
+                "SELECT count(*) FROM lines_read('missing.txt')",
+                [],
+                |_| Ok(())
+            )
+            .is_err());
+        assert_eq!(
+            stats(&conn, "lines_read"),
+            (2, 1, (path.len() + "missing.txt".len()) as i64, 28)
+        );
+        Ok(())
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn test_function_stats_register_all() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        enable_function_stats(&conn)?;
+        crate::register_all(&conn, crate::Families::URL)?;
+        conn.query_row("SELECT url_host('https://example.com/')", [], |_| Ok(()))?;
+        assert_eq!(stats(&conn, "url_host"), (1, 0, 20, 11));
+        Ok(())
+    }
+}
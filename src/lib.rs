@@ -1,9 +1,341 @@
+mod catalog;
+mod fs_policy;
+mod function_stats;
+#[cfg(feature = "loadable_extension")]
+mod loadable;
 mod sqlean_extensions;
+#[cfg(feature = "url")]
 mod sqlite_url;
-#[cfg(not(windows))]
+#[cfg(feature = "lines")]
 mod sqlite_lines;
+mod table_function;
+mod vtab;
 
+use libsqlite3_sys::{sqlite3, sqlite3_api_routines};
+use rusqlite::{ffi, Connection, Error, Result};
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_int};
+
+use catalog::Catalog;
+
+pub use fs_policy::{apply_fs_policy, FsPolicy};
+pub use function_stats::enable_function_stats;
+#[cfg(not(feature = "loadable_extension"))]
 pub use sqlean_extensions::initialize_sqlean_extensions;
+pub use sqlean_extensions::register_sqlean_extensions;
+pub use table_function::{register_table_function, Rows, TableFunction};
+#[cfg(feature = "url")]
 pub use sqlite_url::register_sqlite_url_functions;
-#[cfg(not(windows))]
-pub use sqlite_lines::initialize_sqite_lines_extensions;
\ No newline at end of file
+#[cfg(all(feature = "lines", not(feature = "loadable_extension")))]
+pub use sqlite_lines::initialize_sqite_lines_extensions;
+#[cfg(feature = "lines")]
+pub use sqlite_lines::register_sqlite_lines_extensions;
+#[cfg(feature = "loadable_extension")]
+pub use loadable::sqlite3_surveilrextensions_init;
+
+bitflags::bitflags! {
+    /// Extension families that can be registered on a single connection with [`register_all`].
+    ///
+    /// The bit values are stable, C hosts pass them to [`surveilr_register_on_handle`].
+    #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+    pub struct Families: u32 {
+        /// sqlean `text_*` functions.
+        const TEXT    = 1 << 0;
+        /// sqlean `crypto_*` hashing and encoding functions.
+        const CRYPTO  = 1 << 1;
+        /// sqlean `define` user-defined functions and `eval`.
+        const DEFINE  = 1 << 2;
+        /// sqlean `fileio_*` filesystem functions.
+        const FILEIO  = 1 << 3;
+        /// sqlean `fuzzy_*` string matching functions.
+        const FUZZY   = 1 << 4;
+        /// sqlean `ip*` address functions.
+        const IPADDR  = 1 << 5;
+        /// sqlean `math_*` functions.
+        const MATH    = 1 << 6;
+        /// sqlean `regexp_*` functions (PCRE2).
+        const REGEXP  = 1 << 7;
+        /// sqlean `stats_*` aggregates and sequences.
+        const STATS   = 1 << 8;
+        /// sqlean `time_*` functions.
+        const TIME    = 1 << 9;
+        /// sqlean `unicode` case folding and collation.
+        const UNICODE = 1 << 10;
+        /// sqlean `uuid*` functions.
+        const UUID    = 1 << 11;
+        /// sqlean `vsv` virtual table.
+        const VSV     = 1 << 12;
+        /// `lines` and `lines_read` table functions.
+        const LINES   = 1 << 13;
+        /// `url_*` functions from `sqlite_url`.
+        const URL     = 1 << 14;
+
+        /// Every sqlean family.
+        const SQLEAN = Self::TEXT.bits()
+            | Self::CRYPTO.bits()
+            | Self::DEFINE.bits()
+            | Self::FILEIO.bits()
+            | Self::FUZZY.bits()
+            | Self::IPADDR.bits()
+            | Self::MATH.bits()
+            | Self::REGEXP.bits()
+            | Self::STATS.bits()
+            | Self::TIME.bits()
+            | Self::UNICODE.bits()
+            | Self::UUID.bits()
+            | Self::VSV.bits();
+    }
+}
+
+impl Families {
+    /// Families compiled into this build, as selected by the crate's cargo features.
+    pub fn available() -> Families {
+        [
+            (cfg!(feature = "sqlean-text"), Families::TEXT),
+            (cfg!(feature = "sqlean-crypto"), Families::CRYPTO),
+            (cfg!(feature = "sqlean-define"), Families::DEFINE),
+            (cfg!(feature = "sqlean-fileio"), Families::FILEIO),
+            (cfg!(feature = "sqlean-fuzzy"), Families::FUZZY),
+            (cfg!(feature = "sqlean-ipaddr"), Families::IPADDR),
+            (cfg!(feature = "sqlean-math"), Families::MATH),
+            (cfg!(feature = "sqlean-regexp"), Families::REGEXP),
+            (cfg!(feature = "sqlean-stats"), Families::STATS),
+            (cfg!(feature = "sqlean-time"), Families::TIME),
+            (cfg!(feature = "sqlean-unicode"), Families::UNICODE),
+            (cfg!(feature = "sqlean-uuid"), Families::UUID),
+            (cfg!(feature = "sqlean-vsv"), Families::VSV),
+            (cfg!(feature = "lines"), Families::LINES),
+            (cfg!(feature = "url"), Families::URL),
+        ]
+        .into_iter()
+        .filter(|(enabled, _)| *enabled)
+        .fold(Families::empty(), |families, (_, family)| families | family)
+    }
+}
+
+/// Registers the requested `families` on `conn` only.
+///
+/// Unlike the `initialize_*` functions, nothing is installed as an auto extension, so
+/// other connections in the process are left untouched. Asking for a family that was not
+/// compiled in (see [`Families::available`]) is an error.
+///
+/// Everything registered is listed by the eponymous `surveilr_functions` table: one row per
+/// function overload or virtual-table module with its family, arity, `deterministic` and
+/// `direct_only` flags, upstream `source` and `version` (git commit of the vendored sources).
+/// Which build this is can be answered with `surveilr_build_info`: `version`, `git_sha`,
+/// `sqlean_sha`, `build_date` and the compiled-in `families`, as `(key, value)` rows.
+pub fn register_all(conn: &Connection, families: Families) -> Result<()> {
+    let missing = families - Families::available();
+    if !missing.is_empty() {
+        return Err(Error::ModuleError(format!(
+            "extension families not compiled into this build: {:?}",
+            missing
+        )));
+    }
+
+    let mut catalog = Catalog::load(conn)?;
+    for (name, family) in families.iter_names() {
+        catalog.record(conn, name, family, || register_family(conn, family))?;
+    }
+    catalog.register(conn)
+}
+
+/// Registers the requested `families` on a raw `sqlite3` handle, for hosts that don't hold a
+/// [`Connection`] of this crate's rusqlite version (another rusqlite, sqlx, ...).
+///
+/// Behaves like [`register_all`] on a connection wrapping `db`; the handle stays owned by the
+/// caller and is not closed.
+///
+/// # Safety
+///
+/// `db` must be an open connection of the SQLite library this crate links against (the one
+/// provided by `libsqlite3-sys`, or the host's in loadable mode), and must not be used from
+/// another thread during the call.
+pub unsafe fn register_on_handle(db: *mut sqlite3, families: Families) -> Result<()> {
+    if db.is_null() {
+        return Err(Error::SqliteFailure(
+            ffi::Error::new(ffi::SQLITE_MISUSE),
+            Some("null database handle".to_string()),
+        ));
+    }
+    let conn = Connection::from_handle(db)?;
+    register_all(&conn, families)
+}
+
+/// C entrypoint for [`register_on_handle`]: `families` holds [`Families`] bits (`0xffffffff` for
+/// every family compiled in). Returns an SQLite result code; on failure `*pz_err_msg`, when
+/// not null, receives an error message to be freed with `sqlite3_free`.
+///
+/// # Safety
+///
+/// Same requirements as [`register_on_handle`]; `pz_err_msg` must be null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn surveilr_register_on_handle(
+    db: *mut sqlite3,
+    families: u32,
+    pz_err_msg: *mut *mut c_char,
+) -> c_int {
+    let families = if families == u32::MAX {
+        Ok(Families::available())
+    } else {
+        Families::from_bits(families).ok_or_else(|| {
+            Error::ModuleError(format!("unknown extension family bits: {:#x}", families))
+        })
+    };
+    match families.and_then(|families| register_on_handle(db, families)) {
+        Ok(()) => ffi::SQLITE_OK,
+        Err(err) => report_error(err, pz_err_msg),
+    }
+}
+
+/// Hands `err` to a C caller: its message goes to `*pz_err_msg` (allocated with
+/// `sqlite3_malloc64`) and its result code is returned.
+pub(crate) unsafe fn report_error(err: Error, pz_err_msg: *mut *mut c_char) -> c_int {
+    if !pz_err_msg.is_null() {
+        let message = CString::new(err.to_string().replace('\0', "")).unwrap_or_default();
+        let message = message.as_bytes_with_nul();
+        let copy = ffi::sqlite3_malloc64(message.len() as u64) as *mut c_char;
+        if !copy.is_null() {
+            std::ptr::copy_nonoverlapping(message.as_ptr().cast(), copy, message.len());
+        }
+        *pz_err_msg = copy;
+    }
+    match err {
+        Error::SqliteFailure(err, _) => err.extended_code,
+        _ => ffi::SQLITE_ERROR,
+    }
+}
+
+fn register_family(conn: &Connection, family: Families) -> Result<()> {
+    #[cfg(feature = "lines")]
+    if family == Families::LINES {
+        return register_sqlite_lines_extensions(conn);
+    }
+    #[cfg(feature = "url")]
+    if family == Families::URL {
+        return register_sqlite_url_functions(conn);
+    }
+    register_sqlean_extensions(conn, family)
+}
+
+pub(crate) type ExtensionInit = unsafe extern "C" fn(
+    db: *mut sqlite3,
+    pz_err_msg: *mut *mut c_char,
+    p_api: *const sqlite3_api_routines,
+) -> c_int;
+
+/// Runs a C extension entrypoint against the handle behind `conn`.
+pub(crate) fn run_extension_init(conn: &Connection, init: ExtensionInit) -> Result<()> {
+    // the C extensions always get the instrumented routines: whatever they register with the
+    // first ones they see is called back through them for the life of the process
+    #[cfg(all(feature = "loadable_extension", sqlean))]
+    let api = function_stats::interpose::instrumented_api_routines(loadable::api_routines());
+    #[cfg(all(feature = "loadable_extension", not(sqlean)))]
+    let api = loadable::api_routines();
+    #[cfg(not(feature = "loadable_extension"))]
+    let api = std::ptr::null();
+
+    let mut err_msg: *mut c_char = std::ptr::null_mut();
+    let rc = unsafe { init(conn.handle(), &mut err_msg, api) };
+    if rc == ffi::SQLITE_OK {
+        return Ok(());
+    }
+
+    let message = if err_msg.is_null() {
+        None
+    } else {
+        let message = unsafe { CStr::from_ptr(err_msg) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { ffi::sqlite3_free(err_msg.cast()) };
+        Some(message)
+    };
+    Err(Error::SqliteFailure(ffi::Error::new(rc), message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[cfg(feature = "url")]
+    fn has_function(conn: &Connection, name: &str) -> bool {
+        conn.query_row(
+            "SELECT count(*) FROM pragma_function_list WHERE name = ?",
+            [name],
+            |row| row.get::<_, i64>(0),
+        )
+        .unwrap()
+            > 0
+    }
+
+    #[test]
+    #[cfg(feature = "url")]
+    fn test_register_all_is_per_connection() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        let other = Connection::open_in_memory()?;
+        register_all(&conn, Families::URL)?;
+
+        assert!(has_function(&conn, "url_host"));
+        assert!(!has_function(&other, "url_host"));
+        Ok(())
+    }
+
+    #[test]
+    #[cfg(all(feature = "sqlean-text", feature = "url"))]
+    fn test_register_all_subset() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        register_all(&conn, Families::TEXT | Families::URL)?;
+
+        let upper: String = conn.query_row("SELECT text_upper('abc')", [], |row| row.get(0))?;
+        assert_eq!(upper, "ABC");
+        let host: String = conn.query_row(
+            "SELECT url_host('https://example.com/')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(host, "example.com");
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_all_rejects_missing_families() {
+        let conn = Connection::open_in_memory().unwrap();
+        let missing = Families::all() - Families::available();
+        if !missing.is_empty() {
+            assert!(register_all(&conn, missing).is_err());
+        }
+        assert!(register_all(&conn, Families::empty()).is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "url")]
+    fn test_register_on_handle() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        unsafe { register_on_handle(conn.handle(), Families::URL)? };
+        assert!(has_function(&conn, "url_host"));
+
+        let rc = unsafe { surveilr_register_on_handle(conn.handle(), u32::MAX, ptr::null_mut()) };
+        assert_eq!(rc, ffi::SQLITE_OK);
+        Ok(())
+    }
+
+    #[test]
+    fn test_register_on_handle_errors() {
+        let mut err_msg: *mut c_char = ptr::null_mut();
+        let rc = unsafe { surveilr_register_on_handle(ptr::null_mut(), 0, &mut err_msg) };
+        assert_eq!(rc, ffi::SQLITE_MISUSE);
+        assert!(!err_msg.is_null());
+        unsafe { ffi::sqlite3_free(err_msg.cast()) };
+
+        let conn = Connection::open_in_memory().unwrap();
+        let mut err_msg: *mut c_char = ptr::null_mut();
+        let rc = unsafe { surveilr_register_on_handle(conn.handle(), 1 << 31, &mut err_msg) };
+        assert_eq!(rc, ffi::SQLITE_ERROR);
+        let message = unsafe { CStr::from_ptr(err_msg) }
+            .to_string_lossy()
+            .into_owned();
+        assert!(message.contains("unknown extension family bits"));
+        unsafe { ffi::sqlite3_free(err_msg.cast()) };
+    }
+}
@@ -0,0 +1,42 @@
+use libsqlite3_sys::{sqlite3, sqlite3_api_routines};
+use rusqlite::{Connection, Result};
+use std::os::raw::{c_char, c_int};
+use std::ptr;
+use std::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::{enable_function_stats, register_all, Families};
+
+/// Routines handed over by the host; the C extensions are compiled without `SQLITE_CORE` in this
+/// mode and need them passed to their own entrypoints.
+static API_ROUTINES: AtomicPtr<sqlite3_api_routines> = AtomicPtr::new(ptr::null_mut());
+
+pub(crate) fn api_routines() -> *const sqlite3_api_routines {
+    API_ROUTINES.load(Ordering::Acquire)
+}
+
+/// Entrypoint SQLite derives from `libsurveilr_extensions.{so,dylib,dll}`, so `.load` needs no
+/// explicit entrypoint name. Registers every family on the loading connection, with
+/// [`enable_function_stats`] first when `SURVEILR_FUNCTION_STATS` is set in the environment.
+///
+/// # Safety
+///
+/// Must only be called by SQLite's extension loader with a valid `db` and `p_api`.
+#[no_mangle]
+pub unsafe extern "C" fn sqlite3_surveilrextensions_init(
+    db: *mut sqlite3,
+    pz_err_msg: *mut *mut c_char,
+    p_api: *mut sqlite3_api_routines,
+) -> c_int {
+    if !p_api.is_null() {
+        API_ROUTINES.store(p_api, Ordering::Release);
+    }
+    Connection::extension_init2(db, pz_err_msg, p_api, extension_init)
+}
+
+fn extension_init(conn: Connection) -> Result<bool> {
+    if std::env::var_os("SURVEILR_FUNCTION_STATS").is_some() {
+        enable_function_stats(&conn)?;
+    }
+    register_all(&conn, Families::available())?;
+    Ok(false)
+}
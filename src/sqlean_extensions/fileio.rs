@@ -0,0 +1,811 @@
+//! Rust replacements for the filesystem-touching sqlean `fileio` functions, checked against a
+//! per-connection [`FsPolicy`]. `fileio_mode` doesn't touch the filesystem and stays in C.
+
+use rusqlite::{
+    ffi,
+    functions::{Context, FunctionFlags},
+    types::ValueRef,
+    vtab::{
+        self, eponymous_only_module, CreateVTab, IndexInfo, VTab, VTabConfig, VTabCursor, VTabKind,
+    },
+    Connection, Error, Result,
+};
+use std::fs::{self, File, Metadata, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::os::raw::c_int;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, Instant, UNIX_EPOCH};
+
+use crate::fs_policy::FsPolicy;
+use crate::vtab::{bind_hidden_columns, hidden_column_args};
+
+use crate::function_stats::{
+    create_scalar_function, module_stats, record_callback, value_bytes, FunctionStats,
+};
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFLNK: u32 = 0o120000;
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::UserFunctionError(Box::new(err))
+}
+
+fn arity_error(name: &str, expected: &str) -> Error {
+    Error::UserFunctionError(format!("{}() expects {} arguments", name, expected).into())
+}
+
+/// Bytes of a TEXT or BLOB argument (numbers are written in their text form).
+fn bytes_arg(ctx: &Context, idx: usize) -> Result<Vec<u8>> {
+    Ok(match ctx.get_raw(idx) {
+        ValueRef::Null => vec![],
+        ValueRef::Integer(i) => i.to_string().into_bytes(),
+        ValueRef::Real(f) => f.to_string().into_bytes(),
+        ValueRef::Text(t) | ValueRef::Blob(t) => t.to_vec(),
+    })
+}
+
+fn optional_arg<T: rusqlite::types::FromSql>(ctx: &Context, idx: usize) -> Result<Option<T>> {
+    if idx < ctx.len() {
+        ctx.get(idx)
+    } else {
+        Ok(None)
+    }
+}
+
+fn file_mode(metadata: &Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        metadata.mode()
+    }
+    #[cfg(not(unix))]
+    {
+        let kind = if metadata.is_dir() { S_IFDIR } else { 0o100000 };
+        let perm = if metadata.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        };
+        kind | perm
+    }
+}
+
+#[cfg(unix)]
+fn set_permissions(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode & 0o7777))
+}
+
+#[cfg(not(unix))]
+fn set_permissions(path: &Path, mode: u32) -> std::io::Result<()> {
+    let mut permissions = fs::metadata(path)?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    fs::set_permissions(path, permissions)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, link: &Path) -> std::io::Result<()> {
+    std::os::windows::fs::symlink_file(target, link)
+}
+
+fn read_file(policy: &FsPolicy, ctx: &Context, name: &str) -> Result<Option<Vec<u8>>> {
+    if !(1..=3).contains(&ctx.len()) {
+        return Err(arity_error(name, "1 to 3"));
+    }
+    let path: String = ctx.get(0)?;
+    let offset = optional_arg::<i64>(ctx, 1)?.unwrap_or(0).max(0) as u64;
+    let limit = optional_arg::<i64>(ctx, 2)?.unwrap_or(0).max(0) as u64;
+
+    let resolved = policy.check_read(&path)?;
+    let mut file = match File::open(&resolved) {
+        Ok(file) => file,
+        Err(_) => return Ok(None),
+    };
+    // only the bytes actually read count against the size limit
+    let size = file.metadata().map_err(io_error)?.len();
+    let remaining = size.saturating_sub(offset);
+    let to_read = match limit {
+        0 => remaining,
+        limit => limit.min(remaining),
+    };
+    policy.check_size(&path, to_read)?;
+
+    let mut data = vec![];
+    file.seek(SeekFrom::Start(offset)).map_err(io_error)?;
+    if limit > 0 {
+        file.take(limit).read_to_end(&mut data).map_err(io_error)?;
+    } else {
+        file.read_to_end(&mut data).map_err(io_error)?;
+    }
+    Ok(Some(data))
+}
+
+fn write_file(policy: &FsPolicy, ctx: &Context, name: &str) -> Result<i64> {
+    if !(2..=4).contains(&ctx.len()) {
+        return Err(arity_error(name, "2 to 4"));
+    }
+    let path: String = ctx.get(0)?;
+    let data = bytes_arg(ctx, 1)?;
+    let mode = optional_arg::<i64>(ctx, 2)?.map(|mode| mode as u32);
+    let mtime = optional_arg::<i64>(ctx, 3)?;
+
+    let resolved = policy.check_write(&path)?;
+    match mode.map(|mode| mode & S_IFMT) {
+        Some(S_IFDIR) => {
+            create_dir(&resolved, mode)?;
+            return Ok(0);
+        }
+        Some(S_IFLNK) => {
+            let target = String::from_utf8_lossy(&data).into_owned();
+            check_link_target(policy, &target, &resolved)?;
+            symlink(Path::new(&target), &resolved).map_err(io_error)?;
+            return Ok(0);
+        }
+        _ => {}
+    }
+
+    policy.check_size(&path, data.len() as u64)?;
+    let mut file = File::create(&resolved).map_err(io_error)?;
+    file.write_all(&data).map_err(io_error)?;
+    if let Some(mode) = mode {
+        set_permissions(&resolved, mode).map_err(io_error)?;
+    }
+    if let Some(mtime) = mtime {
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(mtime.max(0) as u64))
+            .map_err(io_error)?;
+    }
+    Ok(data.len() as i64)
+}
+
+/// Checks the target of a symlink about to be created at `link`. A relative target is resolved
+/// the way the OS follows it, from the directory of the link rather than the working directory.
+fn check_link_target(policy: &FsPolicy, target: &str, link: &Path) -> Result<()> {
+    if Path::new(target).is_absolute() {
+        policy.check_read(target)?;
+    } else {
+        let from_link = link.parent().unwrap_or(link).join(target);
+        policy.check_read(&from_link.to_string_lossy())?;
+    }
+    Ok(())
+}
+
+fn create_dir(path: &Path, mode: Option<u32>) -> Result<()> {
+    match fs::create_dir(path) {
+        Ok(()) => {}
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists && path.is_dir() => {}
+        Err(err) => return Err(io_error(err)),
+    }
+    if let Some(mode) = mode {
+        set_permissions(path, mode).map_err(io_error)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn register_fileio_functions(conn: &Connection, policy: Arc<FsPolicy>) -> Result<()> {
+    let flags = FunctionFlags::SQLITE_UTF8 | FunctionFlags::SQLITE_DIRECTONLY;
+
+    for name in ["fileio_read", "readfile"] {
+        let policy = policy.clone();
+        create_scalar_function(conn, name, -1, flags, move |ctx| {
+            read_file(&policy, ctx, name)
+        })?;
+    }
+
+    for name in ["fileio_write", "writefile"] {
+        let policy = policy.clone();
+        create_scalar_function(conn, name, -1, flags, move |ctx| {
+            write_file(&policy, ctx, name)
+        })?;
+    }
+
+    let append_policy = policy.clone();
+    create_scalar_function(conn, "fileio_append", 2, flags, move |ctx| {
+        let path: String = ctx.get(0)?;
+        let data = bytes_arg(ctx, 1)?;
+        let resolved = append_policy.check_write(&path)?;
+        let existing = fs::metadata(&resolved).map(|meta| meta.len()).unwrap_or(0);
+        append_policy.check_size(&path, existing + data.len() as u64)?;
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&resolved)
+            .map_err(io_error)?;
+        file.write_all(&data).map_err(io_error)?;
+        Ok(data.len() as i64)
+    })?;
+
+    let mkdir_policy = policy.clone();
+    create_scalar_function(conn, "fileio_mkdir", -1, flags, move |ctx| {
+        if !(1..=2).contains(&ctx.len()) {
+            return Err(arity_error("fileio_mkdir", "1 or 2"));
+        }
+        let path: String = ctx.get(0)?;
+        let mode = optional_arg::<i64>(ctx, 1)?.map(|mode| mode as u32);
+        let resolved = mkdir_policy.check_write(&path)?;
+        create_dir(&resolved, mode)?;
+        Ok(None::<i64>)
+    })?;
+
+    let symlink_policy = policy.clone();
+    create_scalar_function(conn, "fileio_symlink", 2, flags, move |ctx| {
+        let target: String = ctx.get(0)?;
+        let link: String = ctx.get(1)?;
+        let resolved = symlink_policy.check_write(&link)?;
+        // the target is checked too, otherwise a link could expose a path outside the roots
+        check_link_target(&symlink_policy, &target, &resolved)?;
+        symlink(Path::new(&target), &resolved).map_err(io_error)?;
+        Ok(None::<i64>)
+    })?;
+
+    for name in ["fileio_ls", "lsdir"] {
+        conn.create_module(
+            name,
+            eponymous_only_module::<LsTable>(),
+            Some(ModuleAux {
+                policy: policy.clone(),
+                stats: module_stats(conn, name, 2),
+            }),
+        )?;
+    }
+    conn.create_module(
+        "fileio_scan",
+        eponymous_only_module::<ScanTable>(),
+        Some(ModuleAux {
+            stats: module_stats(conn, "fileio_scan", 1),
+            policy,
+        }),
+    )?;
+    Ok(())
+}
+
+/// What the fileio modules are registered with.
+struct ModuleAux {
+    policy: Arc<FsPolicy>,
+    stats: Option<Arc<FunctionStats>>,
+}
+
+impl ModuleAux {
+    fn policy(aux: Option<&ModuleAux>) -> Arc<FsPolicy> {
+        aux.map_or_else(|| Arc::new(FsPolicy::new()), |aux| aux.policy.clone())
+    }
+
+    fn stats(aux: Option<&ModuleAux>) -> Option<Arc<FunctionStats>> {
+        aux.and_then(|aux| aux.stats.clone())
+    }
+}
+
+#[repr(C)]
+struct LsTable {
+    base: ffi::sqlite3_vtab,
+    policy: Arc<FsPolicy>,
+    stats: Option<Arc<FunctionStats>>,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for LsTable {
+    type Aux = ModuleAux;
+    type Cursor = LsCursor;
+
+    fn connect(
+        db: &mut vtab::VTabConnection,
+        aux: Option<&Self::Aux>,
+        _args: &[&[u8]],
+    ) -> Result<(String, Self)> {
+        db.config(VTabConfig::DirectOnly)?;
+        let schema = "CREATE TABLE x(name text, mode integer, mtime integer, size integer, path hidden, recursive hidden)";
+        Ok((
+            schema.to_string(),
+            LsTable {
+                base: ffi::sqlite3_vtab::default(),
+                policy: ModuleAux::policy(aux),
+                stats: ModuleAux::stats(aux),
+            },
+        ))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
+        bind_hidden_columns(info, 4, 2)
+    }
+
+    fn open(&mut self) -> Result<Self::Cursor> {
+        Ok(LsCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            policy: self.policy.clone(),
+            stats: self.stats.clone(),
+            rows: vec![],
+            index: 0,
+        })
+    }
+}
+
+impl CreateVTab<'_> for LsTable {
+    const KIND: VTabKind = VTabKind::EponymousOnly;
+}
+
+struct LsRow {
+    name: String,
+    mode: u32,
+    mtime: i64,
+    size: u64,
+}
+
+#[repr(C)]
+struct LsCursor {
+    base: ffi::sqlite3_vtab_cursor,
+    policy: Arc<FsPolicy>,
+    stats: Option<Arc<FunctionStats>>,
+    rows: Vec<LsRow>,
+    index: usize,
+}
+
+fn list(name: String, path: &Path, recursive: bool, top: bool, rows: &mut Vec<LsRow>) {
+    let Ok(metadata) = fs::symlink_metadata(path) else {
+        return;
+    };
+    let mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |time| time.as_secs() as i64);
+    rows.push(LsRow {
+        name: name.clone(),
+        mode: file_mode(&metadata),
+        mtime,
+        size: metadata.len(),
+    });
+
+    // symlinked directories are listed but never followed
+    if metadata.is_dir() && (top || recursive) {
+        let Ok(entries) = fs::read_dir(path) else {
+            return;
+        };
+        let mut entries: Vec<_> = entries.filter_map(|entry| entry.ok()).collect();
+        entries.sort_by_key(|entry| entry.file_name());
+        for entry in entries {
+            let child = format!(
+                "{}/{}",
+                name.trim_end_matches('/'),
+                entry.file_name().to_string_lossy()
+            );
+            list(child, &entry.path(), recursive, false, rows);
+        }
+    }
+}
+
+impl LsCursor {
+    /// Lists the entries in `filter`, which is all the work this cursor does.
+    fn list(&mut self, idx_num: c_int, args: &vtab::Values<'_>) -> Result<()> {
+        let positions = hidden_column_args(idx_num, 2);
+        let Some(path_arg) = positions[0] else {
+            return Err(Error::ModuleError(
+                "Missing required path argument.".to_string(),
+            ));
+        };
+        let path: String = args.get(path_arg)?;
+        let recursive = match positions[1] {
+            Some(arg) => args.get::<bool>(arg)?,
+            None => false,
+        };
+
+        let resolved: PathBuf = self.policy.check_read(&path)?;
+        self.rows = vec![];
+        list(path, &resolved, recursive, true, &mut self.rows);
+        self.index = 0;
+        Ok(())
+    }
+}
+
+unsafe impl VTabCursor for LsCursor {
+    fn filter(
+        &mut self,
+        idx_num: c_int,
+        _idx_str: Option<&str>,
+        args: &vtab::Values<'_>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        self.rows = vec![];
+        let result = self.list(idx_num, args);
+        let bytes_in = args.iter().map(value_bytes).sum();
+        let bytes_out = self.rows.iter().map(|row| row.name.len() as u64).sum();
+        record_callback(
+            &self.stats,
+            true,
+            start,
+            bytes_in,
+            bytes_out,
+            result.is_err(),
+        );
+        result
+    }
+
+    fn next(&mut self) -> Result<()> {
+        self.index += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.index >= self.rows.len()
+    }
+
+    fn column(&self, ctx: &mut vtab::Context, col: i32) -> Result<()> {
+        let row = &self.rows[self.index];
+        match col {
+            0 => ctx.set_result(&row.name),
+            1 => ctx.set_result(&row.mode),
+            2 => ctx.set_result(&row.mtime),
+            3 => ctx.set_result(&(row.size as i64)),
+            _ => Ok(()),
+        }
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.index as i64 + 1)
+    }
+}
+
+#[repr(C)]
+struct ScanTable {
+    base: ffi::sqlite3_vtab,
+    policy: Arc<FsPolicy>,
+    stats: Option<Arc<FunctionStats>>,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for ScanTable {
+    type Aux = ModuleAux;
+    type Cursor = ScanCursor;
+
+    fn connect(
+        db: &mut vtab::VTabConnection,
+        aux: Option<&Self::Aux>,
+        _args: &[&[u8]],
+    ) -> Result<(String, Self)> {
+        db.config(VTabConfig::DirectOnly)?;
+        let schema = "CREATE TABLE x(name text, value text, path hidden)";
+        Ok((
+            schema.to_string(),
+            ScanTable {
+                base: ffi::sqlite3_vtab::default(),
+                policy: ModuleAux::policy(aux),
+                stats: ModuleAux::stats(aux),
+            },
+        ))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
+        bind_hidden_columns(info, 2, 1)
+    }
+
+    fn open(&mut self) -> Result<Self::Cursor> {
+        Ok(ScanCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            policy: self.policy.clone(),
+            stats: self.stats.clone(),
+            name: String::new(),
+            reader: None,
+            line: None,
+            rowid: 0,
+        })
+    }
+}
+
+impl CreateVTab<'_> for ScanTable {
+    const KIND: VTabKind = VTabKind::EponymousOnly;
+}
+
+#[repr(C)]
+struct ScanCursor {
+    base: ffi::sqlite3_vtab_cursor,
+    policy: Arc<FsPolicy>,
+    stats: Option<Arc<FunctionStats>>,
+    name: String,
+    reader: Option<BufReader<File>>,
+    line: Option<Vec<u8>>,
+    rowid: i64,
+}
+
+impl ScanCursor {
+    fn open(&mut self, idx_num: c_int, args: &vtab::Values<'_>) -> Result<()> {
+        let Some(path_arg) = hidden_column_args(idx_num, 1)[0] else {
+            return Err(Error::ModuleError(
+                "Missing required path argument.".to_string(),
+            ));
+        };
+        let path: String = args.get(path_arg)?;
+        let resolved = self.policy.check_read(&path)?;
+        let file = File::open(&resolved).map_err(|err| Error::ModuleError(err.to_string()))?;
+        let size = file
+            .metadata()
+            .map_err(|err| Error::ModuleError(err.to_string()))?
+            .len();
+        self.policy.check_size(&path, size)?;
+
+        self.name = path;
+        self.reader = Some(BufReader::new(file));
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<()> {
+        self.line = None;
+        let Some(reader) = self.reader.as_mut() else {
+            return Ok(());
+        };
+        let mut line = vec![];
+        let read = reader
+            .read_until(b'\n', &mut line)
+            .map_err(|err| Error::ModuleError(err.to_string()))?;
+        if read > 0 {
+            if line.ends_with(b"\n") {
+                line.pop();
+            }
+            self.rowid += 1;
+            self.line = Some(line);
+        }
+        Ok(())
+    }
+}
+
+unsafe impl VTabCursor for ScanCursor {
+    fn filter(
+        &mut self,
+        idx_num: c_int,
+        _idx_str: Option<&str>,
+        args: &vtab::Values<'_>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self.open(idx_num, args);
+        let bytes_in = args.iter().map(value_bytes).sum();
+        record_callback(&self.stats, true, start, bytes_in, 0, result.is_err());
+        result?;
+        self.next()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.read_line();
+        let bytes_out = self.line.as_ref().map_or(0, |line| line.len() as u64);
+        record_callback(&self.stats, false, start, 0, bytes_out, result.is_err());
+        result
+    }
+
+    fn eof(&self) -> bool {
+        self.line.is_none()
+    }
+
+    fn column(&self, ctx: &mut vtab::Context, col: i32) -> Result<()> {
+        match col {
+            0 => ctx.set_result(&self.name),
+            1 => ctx.set_result(&String::from_utf8_lossy(
+                self.line.as_deref().unwrap_or(&[]),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn setup(policy: FsPolicy) -> (Connection, PathBuf) {
+        let root = std::env::temp_dir().join(format!(
+            "surveilr-fileio-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(&root).unwrap();
+        let root = root.canonicalize().unwrap();
+
+        let conn = Connection::open_in_memory().unwrap();
+        register_fileio_functions(&conn, Arc::new(policy.allow_root(&root))).unwrap();
+        (conn, root)
+    }
+
+    fn path(root: &Path, name: &str) -> String {
+        root.join(name).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn test_fileio_read_write() -> Result<()> {
+        let (conn, root) = setup(FsPolicy::new().read_write());
+        let hello = path(&root, "hello.txt");
+
+        let written: i64 =
+            conn.query_row("SELECT fileio_write(?, 'hello world')", [&hello], |row| {
+                row.get(0)
+            })?;
+        assert_eq!(written, 11);
+        let read: String = conn.query_row(
+            "SELECT cast(fileio_read(?, 6, 3) as text)",
+            [&hello],
+            |row| row.get(0),
+        )?;
+        assert_eq!(read, "wor");
+        let missing: Option<Vec<u8>> = conn.query_row(
+            "SELECT fileio_read(?)",
+            [path(&root, "missing.txt")],
+            |row| row.get(0),
+        )?;
+        assert_eq!(missing, None);
+
+        let appended: i64 =
+            conn.query_row("SELECT fileio_append(?, '!')", [&hello], |row| row.get(0))?;
+        assert_eq!(appended, 1);
+
+        let lines: Vec<(i64, String)> = {
+            fs::write(&hello, "one\ntwo\n").unwrap();
+            conn.prepare("SELECT rowid, value FROM fileio_scan(?)")?
+                .query_map([&hello], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<Result<_>>()?
+        };
+        assert_eq!(lines, vec![(1, "one".to_string()), (2, "two".to_string())]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fileio_ls() -> Result<()> {
+        let (conn, root) = setup(FsPolicy::new().read_write());
+        let dir = path(&root, "parentdir");
+        conn.query_row("SELECT fileio_mkdir(?)", [&dir], |_| Ok(()))?;
+        fs::write(root.join("parentdir/parent.txt"), "").unwrap();
+        fs::create_dir(root.join("parentdir/subdir")).unwrap();
+        fs::write(root.join("parentdir/subdir/child.txt"), "").unwrap();
+
+        let count = |sql: &str| -> Result<i64> { conn.query_row(sql, [&dir], |row| row.get(0)) };
+        assert_eq!(count("SELECT count(*) FROM fileio_ls(?)")?, 3);
+        assert_eq!(count("SELECT count(*) FROM fileio_ls(?, true)")?, 4);
+        Ok(())
+    }
+
+    #[test]
+    fn test_fileio_module_stats() -> Result<()> {
+        let (_, root) = setup(FsPolicy::new());
+        fs::write(root.join("lines.txt"), "one\ntwo\n").unwrap();
+        let conn = Connection::open_in_memory()?;
+        crate::enable_function_stats(&conn)?;
+        register_fileio_functions(&conn, Arc::new(FsPolicy::new().allow_root(&root)))?;
+
+        let root = root.to_str().unwrap();
+        let lines = path(Path::new(root), "lines.txt");
+        conn.query_row("SELECT count(*) FROM fileio_ls(?)", [root], |_| Ok(()))?;
+        conn.query_row("SELECT count(*) FROM fileio_scan(?)", [&lines], |_| Ok(()))?;
+        assert!(conn
+            .query_row(
+                "SELECT count(*) FROM fileio_scan('/etc/passwd')",
+                [],
+                |_| Ok(())
+            )
+            .is_err());
+
+        let stats = |name: &str| -> Result<(i64, i64, i64, i64)> {
+            conn.query_row(
+                "SELECT calls, errors, bytes_in, bytes_out FROM surveilr_function_stats
+                 WHERE name = ?",
+                [name],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+        };
+        let bytes_out = (root.len() + lines.len()) as i64;
+        assert_eq!(stats("fileio_ls")?, (1, 0, root.len() as i64, bytes_out));
+        assert_eq!(
+            stats("fileio_scan")?,
+            (2, 1, (lines.len() + "/etc/passwd".len()) as i64, 6)
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_fileio_symlink_targets() -> Result<()> {
+        let (conn, root) = setup(FsPolicy::new().read_write());
+        fs::write(root.join("hello.txt"), "hello").unwrap();
+        fs::create_dir(root.join("a")).unwrap();
+
+        // relative targets are followed from the directory of the link
+        conn.query_row(
+            "SELECT fileio_symlink('../hello.txt', ?)",
+            [path(&root, "a/link")],
+            |_| Ok(()),
+        )?;
+        let read: String = conn.query_row(
+            "SELECT cast(fileio_read(?) as text)",
+            [path(&root, "a/link")],
+            |row| row.get(0),
+        )?;
+        assert_eq!(read, "hello");
+
+        // climbing to `/` from the working directory and back down into the root stays inside,
+        // but from a link deeper than the working directory it ends up outside
+        let depth = |path: &Path| path.components().count() - 1;
+        let cwd_depth = depth(&std::env::current_dir().unwrap());
+        let mut link_dir = root.clone();
+        while depth(&link_dir) <= cwd_depth.max(depth(&root)) {
+            link_dir.push("d");
+        }
+        fs::create_dir_all(&link_dir).unwrap();
+        let target = format!(
+            "{}{}",
+            "../".repeat(cwd_depth),
+            root.join("hello.txt").strip_prefix("/").unwrap().display()
+        );
+        let link = path(&link_dir, "escape");
+        for sql in [
+            "SELECT fileio_symlink(?1, ?2)",
+            "SELECT fileio_write(?2, ?1, 40960)",
+        ] {
+            assert!(conn.query_row(sql, [&target, &link], |_| Ok(())).is_err());
+            assert!(fs::symlink_metadata(&link).is_err());
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_fileio_policy_violations() {
+        let (conn, root) = setup(FsPolicy::new().max_file_size(4));
+        fs::write(root.join("big.txt"), "hello world").unwrap();
+
+        assert!(conn
+            .query_row("SELECT fileio_read('/etc/passwd')", [], |_| Ok(()))
+            .is_err());
+        assert!(conn
+            .query_row("SELECT count(*) FROM fileio_ls('/etc')", [], |_| Ok(()))
+            .is_err());
+        assert!(conn
+            .query_row(
+                "SELECT fileio_read(?)",
+                [path(&root, "big.txt")],
+                |_| Ok(())
+            )
+            .is_err());
+        // a bounded read of a file over the limit is fine
+        let read: String = conn
+            .query_row(
+                "SELECT cast(fileio_read(?, 6, 4) as text) || cast(fileio_read(?, 8) as text)",
+                [path(&root, "big.txt"), path(&root, "big.txt")],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(read, "worlrld");
+        assert!(conn
+            .query_row(
+                "SELECT fileio_read(?, 1, 5)",
+                [path(&root, "big.txt")],
+                |_| Ok(())
+            )
+            .is_err());
+        assert!(conn
+            .query_row(
+                "SELECT fileio_write(?, 'x')",
+                [path(&root, "new.txt")],
+                |_| Ok(())
+            )
+            .is_err());
+    }
+
+    #[test]
+    fn test_fileio_direct_only() {
+        let (conn, root) = setup(FsPolicy::new());
+        let hello = path(&root, "hello.txt");
+        fs::write(&hello, "hello").unwrap();
+
+        conn.execute_batch(&format!(
+            "CREATE VIEW v AS SELECT fileio_read('{}') AS data",
+            hello
+        ))
+        .unwrap();
+        assert!(conn
+            .query_row("SELECT data FROM v", [], |_| Ok(()))
+            .is_err());
+    }
+}
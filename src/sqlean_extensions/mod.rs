@@ -1,26 +1,78 @@
+#[cfg(not(feature = "loadable_extension"))]
 use libsqlite3_sys::sqlite3_auto_extension;
+use rusqlite::{Connection, Result};
 
-mod bindings;
+use crate::{run_extension_init, ExtensionInit, Families};
 
+#[cfg(sqlean)]
+mod bindings;
+#[cfg(feature = "sqlean-fileio")]
+mod fileio;
+
+#[cfg(feature = "sqlean-fileio")]
+pub(crate) use fileio::register_fileio_functions;
+
+const SQLEAN_EXTENSIONS: &[(Families, ExtensionInit)] = &[
+    #[cfg(feature = "sqlean-text")]
+    (Families::TEXT, bindings::sqlite3_text_init),
+    #[cfg(feature = "sqlean-crypto")]
+    (Families::CRYPTO, bindings::sqlite3_crypto_init),
+    #[cfg(feature = "sqlean-define")]
+    (Families::DEFINE, bindings::sqlite3_define_init),
+    #[cfg(feature = "sqlean-fileio")]
+    (Families::FILEIO, bindings::sqlite3_fileio_init),
+    #[cfg(feature = "sqlean-fuzzy")]
+    (Families::FUZZY, bindings::sqlite3_fuzzy_init),
+    #[cfg(feature = "sqlean-ipaddr")]
+    (Families::IPADDR, bindings::sqlite3_ipaddr_init),
+    #[cfg(feature = "sqlean-math")]
+    (Families::MATH, bindings::sqlite3_math_init),
+    #[cfg(feature = "sqlean-stats")]
+    (Families::STATS, bindings::sqlite3_stats_init),
+    #[cfg(feature = "sqlean-time")]
+    (Families::TIME, bindings::sqlite3_time_init),
+    #[cfg(feature = "sqlean-unicode")]
+    (Families::UNICODE, bindings::sqlite3_unicode_init),
+    #[cfg(feature = "sqlean-uuid")]
+    (Families::UUID, bindings::sqlite3_uuid_init),
+    #[cfg(feature = "sqlean-vsv")]
+    (Families::VSV, bindings::sqlite3_vsv_init),
+    #[cfg(feature = "sqlean-regexp")]
+    (Families::REGEXP, bindings::sqlite3_regexp_init),
+];
+
+// a loaded extension has no business installing process-wide auto extensions in its host
+#[cfg(not(feature = "loadable_extension"))]
 pub fn initialize_sqlean_extensions() {
     unsafe {
-        sqlite3_auto_extension(Some(bindings::sqlite3_text_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_crypto_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_define_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_fileio_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_fuzzy_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_ipaddr_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_math_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_stats_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_time_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_unicode_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_uuid_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_vsv_init));
-        sqlite3_auto_extension(Some(bindings::sqlite3_regexp_init));
+        for (_, init) in SQLEAN_EXTENSIONS {
+            sqlite3_auto_extension(Some(*init));
+        }
+    }
+}
+
+/// Registers the requested sqlean families on `conn` only.
+///
+/// The filesystem-touching `fileio` functions are replaced by direct-only Rust versions that
+/// honour the connection's [`FsPolicy`](crate::FsPolicy), unrestricted until
+/// [`apply_fs_policy`](crate::apply_fs_policy) says otherwise.
+pub fn register_sqlean_extensions(conn: &Connection, families: Families) -> Result<()> {
+    for (family, init) in SQLEAN_EXTENSIONS {
+        if families.contains(*family) {
+            run_extension_init(conn, *init)?;
+        }
+    }
+    #[cfg(feature = "sqlean-fileio")]
+    if families.contains(Families::FILEIO) {
+        register_fileio_functions(
+            conn,
+            std::sync::Arc::new(crate::FsPolicy::unrestricted()),
+        )?;
     }
+    Ok(())
 }
 
-#[cfg(test)]
+#[cfg(all(test, sqlean, not(feature = "loadable_extension")))]
 mod tests {
     use rusqlite::{Connection, Result};
 
@@ -35,6 +87,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-text")]
     fn text_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -45,6 +98,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-crypto")]
     fn crypto_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -55,6 +109,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-define")]
     fn define_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -65,6 +120,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-fileio")]
     fn fileio_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -75,6 +131,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-fuzzy")]
     fn fuzzy_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -85,6 +142,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-ipaddr")]
     fn ipaddr_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -95,6 +153,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-math")]
     fn math_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -105,6 +164,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-regexp")]
     fn regexp_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -115,6 +175,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean")]
     fn sqlean_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -125,6 +186,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-stats")]
     fn stats_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -135,6 +197,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-time")]
     fn time_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -145,6 +208,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-unicode")]
     fn unicode_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -155,6 +219,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(feature = "sqlean-uuid")]
     fn uuid_functions() {
         initialize_sqlean_extensions();
         let conn = Connection::open_in_memory().unwrap();
@@ -0,0 +1,239 @@
+use rusqlite::{
+    ffi,
+    types::Value,
+    vtab::{self, eponymous_only_module, CreateVTab, IndexInfo, VTab, VTabCursor, VTabKind},
+    Connection, Error, Result,
+};
+use std::io::{BufRead, Cursor};
+use std::os::raw::c_int;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::function_stats::{module_stats, record_callback, value_bytes, FunctionStats};
+use crate::vtab::{bind_hidden_columns, hidden_column_args};
+
+/// Reads `reader` one `delimiter`-terminated line at a time. A trailing delimiter doesn't produce
+/// an empty last line, and with `\n` a trailing `\r` is dropped as well.
+pub(crate) struct DelimitedLines<R> {
+    reader: R,
+    delimiter: Vec<u8>,
+}
+
+impl<R: BufRead> DelimitedLines<R> {
+    pub(crate) fn new(reader: R, delimiter: Vec<u8>) -> Self {
+        DelimitedLines { reader, delimiter }
+    }
+
+    pub(crate) fn next_line(&mut self) -> std::io::Result<Option<Vec<u8>>> {
+        let Some(&last) = self.delimiter.last() else {
+            return Ok(None);
+        };
+
+        let mut line = vec![];
+        loop {
+            let read = self.reader.read_until(last, &mut line)?;
+            if read == 0 {
+                break;
+            }
+            if line.ends_with(&self.delimiter) {
+                line.truncate(line.len() - self.delimiter.len());
+                if self.delimiter == b"\n" && line.ends_with(b"\r") {
+                    line.pop();
+                }
+                return Ok(Some(line));
+            }
+        }
+        Ok((!line.is_empty()).then_some(line))
+    }
+}
+
+/// Reads the optional `delimiter` argument of `lines` and `lines_read`, `\n` by default.
+pub(crate) fn delimiter_arg(args: &vtab::Values<'_>, position: Option<usize>) -> Result<String> {
+    let delimiter = match position {
+        Some(arg) => args.get::<String>(arg)?,
+        None => "\n".to_string(),
+    };
+    if delimiter.is_empty() {
+        return Err(Error::ModuleError(
+            "Delimiter must not be empty.".to_string(),
+        ));
+    }
+    Ok(delimiter)
+}
+
+#[repr(C)]
+struct LinesTable {
+    base: ffi::sqlite3_vtab,
+    stats: Option<Arc<FunctionStats>>,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for LinesTable {
+    type Aux = Option<Arc<FunctionStats>>;
+    type Cursor = LinesCursor;
+
+    fn connect(
+        _db: &mut vtab::VTabConnection,
+        aux: Option<&Self::Aux>,
+        _args: &[&[u8]],
+    ) -> Result<(String, Self)> {
+        let schema = "CREATE TABLE x(line text, document hidden, delimiter hidden)";
+        Ok((
+            schema.to_string(),
+            LinesTable {
+                base: ffi::sqlite3_vtab::default(),
+                stats: aux.cloned().flatten(),
+            },
+        ))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
+        bind_hidden_columns(info, 1, 2)
+    }
+
+    fn open(&mut self) -> Result<Self::Cursor> {
+        Ok(LinesCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            stats: self.stats.clone(),
+            document: Value::Null,
+            delimiter: String::new(),
+            lines: None,
+            line: None,
+            rowid: 0,
+        })
+    }
+}
+
+impl CreateVTab<'_> for LinesTable {
+    const KIND: VTabKind = VTabKind::EponymousOnly;
+}
+
+#[repr(C)]
+struct LinesCursor {
+    base: ffi::sqlite3_vtab_cursor,
+    stats: Option<Arc<FunctionStats>>,
+    document: Value,
+    delimiter: String,
+    lines: Option<DelimitedLines<Cursor<Vec<u8>>>>,
+    line: Option<Vec<u8>>,
+    rowid: i64,
+}
+
+impl LinesCursor {
+    fn open(&mut self, idx_num: c_int, args: &vtab::Values<'_>) -> Result<()> {
+        let positions = hidden_column_args(idx_num, 2);
+        let Some(document_arg) = positions[0] else {
+            return Err(Error::ModuleError(
+                "Missing required document argument.".to_string(),
+            ));
+        };
+        let document: Value = args.get(document_arg)?;
+        let delimiter = delimiter_arg(args, positions[1])?;
+
+        let bytes = match &document {
+            Value::Text(text) => Some(text.as_bytes().to_vec()),
+            Value::Blob(blob) => Some(blob.clone()),
+            Value::Null => None,
+            _ => {
+                return Err(Error::ModuleError(
+                    "Document must be text or a blob.".to_string(),
+                ))
+            }
+        };
+
+        self.lines = bytes
+            .map(|bytes| DelimitedLines::new(Cursor::new(bytes), delimiter.as_bytes().to_vec()));
+        self.document = document;
+        self.delimiter = delimiter;
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<()> {
+        self.line = match self.lines.as_mut() {
+            Some(lines) => lines
+                .next_line()
+                .map_err(|err| Error::ModuleError(err.to_string()))?,
+            None => None,
+        };
+        self.rowid += 1;
+        Ok(())
+    }
+}
+
+unsafe impl VTabCursor for LinesCursor {
+    fn filter(
+        &mut self,
+        idx_num: c_int,
+        _idx_str: Option<&str>,
+        args: &vtab::Values<'_>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self.open(idx_num, args);
+        let bytes_in = args.iter().map(value_bytes).sum();
+        record_callback(&self.stats, true, start, bytes_in, 0, result.is_err());
+        result?;
+        self.next()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.read_line();
+        let bytes_out = self.line.as_ref().map_or(0, |line| line.len() as u64);
+        record_callback(&self.stats, false, start, 0, bytes_out, result.is_err());
+        result
+    }
+
+    fn eof(&self) -> bool {
+        self.line.is_none()
+    }
+
+    fn column(&self, ctx: &mut vtab::Context, col: i32) -> Result<()> {
+        match col {
+            0 => ctx.set_result(&String::from_utf8_lossy(
+                self.line.as_deref().unwrap_or(&[]),
+            )),
+            1 => ctx.set_result(&self.document),
+            2 => ctx.set_result(&self.delimiter),
+            _ => Ok(()),
+        }
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+pub(crate) fn register_lines(conn: &Connection) -> Result<()> {
+    conn.create_module(
+        "lines",
+        eponymous_only_module::<LinesTable>(),
+        Some(module_stats(conn, "lines", 2)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(document: &str, delimiter: &str) -> Vec<String> {
+        let mut lines = DelimitedLines::new(
+            Cursor::new(document.as_bytes().to_vec()),
+            delimiter.as_bytes().to_vec(),
+        );
+        let mut result = vec![];
+        while let Some(line) = lines.next_line().unwrap() {
+            result.push(String::from_utf8(line).unwrap());
+        }
+        result
+    }
+
+    #[test]
+    fn test_delimited_lines() {
+        assert_eq!(split("a\nb\n", "\n"), vec!["a", "b"]);
+        assert_eq!(split("a\r\nb\r\n", "\n"), vec!["a", "b"]);
+        assert_eq!(split("a\n\nb", "\n"), vec!["a", "", "b"]);
+        assert_eq!(split("axxbxxxc", "xx"), vec!["a", "b", "xc"]);
+        assert_eq!(split("a|b\r|", "|"), vec!["a", "b\r"]);
+        assert_eq!(split("", "\n"), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,173 @@
+use rusqlite::{
+    ffi,
+    vtab::{
+        self, eponymous_only_module, CreateVTab, IndexInfo, VTab, VTabConfig, VTabCursor, VTabKind,
+    },
+    Connection, Error, Result,
+};
+use std::fs::File;
+use std::io::BufReader;
+use std::os::raw::c_int;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::lines::{delimiter_arg, DelimitedLines};
+use crate::fs_policy::FsPolicy;
+use crate::function_stats::{module_stats, record_callback, value_bytes, FunctionStats};
+use crate::vtab::{bind_hidden_columns, hidden_column_args};
+
+#[repr(C)]
+struct LinesReadTable {
+    base: ffi::sqlite3_vtab,
+    policy: Arc<FsPolicy>,
+    stats: Option<Arc<FunctionStats>>,
+}
+
+unsafe impl<'vtab> VTab<'vtab> for LinesReadTable {
+    type Aux = (Arc<FsPolicy>, Option<Arc<FunctionStats>>);
+    type Cursor = LinesReadCursor;
+
+    fn connect(
+        db: &mut vtab::VTabConnection,
+        aux: Option<&Self::Aux>,
+        _args: &[&[u8]],
+    ) -> Result<(String, Self)> {
+        db.config(VTabConfig::DirectOnly)?;
+        let schema = "CREATE TABLE x(line text, path hidden, delimiter hidden)";
+        Ok((
+            schema.to_string(),
+            LinesReadTable {
+                base: ffi::sqlite3_vtab::default(),
+                policy: aux.map_or_else(|| Arc::new(FsPolicy::new()), |(policy, _)| policy.clone()),
+                stats: aux.and_then(|(_, stats)| stats.clone()),
+            },
+        ))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
+        bind_hidden_columns(info, 1, 2)
+    }
+
+    fn open(&mut self) -> Result<Self::Cursor> {
+        Ok(LinesReadCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            policy: self.policy.clone(),
+            stats: self.stats.clone(),
+            path: String::new(),
+            delimiter: String::new(),
+            lines: None,
+            line: None,
+            rowid: 0,
+        })
+    }
+}
+
+impl CreateVTab<'_> for LinesReadTable {
+    const KIND: VTabKind = VTabKind::EponymousOnly;
+}
+
+#[repr(C)]
+struct LinesReadCursor {
+    base: ffi::sqlite3_vtab_cursor,
+    policy: Arc<FsPolicy>,
+    stats: Option<Arc<FunctionStats>>,
+    path: String,
+    delimiter: String,
+    lines: Option<DelimitedLines<BufReader<File>>>,
+    line: Option<Vec<u8>>,
+    rowid: i64,
+}
+
+impl LinesReadCursor {
+    fn open(&mut self, idx_num: c_int, args: &vtab::Values<'_>) -> Result<()> {
+        let positions = hidden_column_args(idx_num, 2);
+        let Some(path_arg) = positions[0] else {
+            return Err(Error::ModuleError(
+                "Missing required path argument.".to_string(),
+            ));
+        };
+        let path: String = args.get(path_arg)?;
+        let delimiter = delimiter_arg(args, positions[1])?;
+
+        let resolved = self.policy.check_read(&path)?;
+        let file = File::open(&resolved)
+            .map_err(|err| Error::ModuleError(format!("Error reading {}: {}", path, err)))?;
+        let size = file
+            .metadata()
+            .map_err(|err| Error::ModuleError(err.to_string()))?
+            .len();
+        self.policy.check_size(&path, size)?;
+
+        self.lines = Some(DelimitedLines::new(
+            BufReader::new(file),
+            delimiter.as_bytes().to_vec(),
+        ));
+        self.path = path;
+        self.delimiter = delimiter;
+        self.rowid = 0;
+        Ok(())
+    }
+
+    fn read_line(&mut self) -> Result<()> {
+        self.line = match self.lines.as_mut() {
+            Some(lines) => lines
+                .next_line()
+                .map_err(|err| Error::ModuleError(err.to_string()))?,
+            None => None,
+        };
+        self.rowid += 1;
+        Ok(())
+    }
+}
+
+unsafe impl VTabCursor for LinesReadCursor {
+    fn filter(
+        &mut self,
+        idx_num: c_int,
+        _idx_str: Option<&str>,
+        args: &vtab::Values<'_>,
+    ) -> Result<()> {
+        let start = Instant::now();
+        let result = self.open(idx_num, args);
+        let bytes_in = args.iter().map(value_bytes).sum();
+        record_callback(&self.stats, true, start, bytes_in, 0, result.is_err());
+        result?;
+        self.next()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = self.read_line();
+        let bytes_out = self.line.as_ref().map_or(0, |line| line.len() as u64);
+        record_callback(&self.stats, false, start, 0, bytes_out, result.is_err());
+        result
+    }
+
+    fn eof(&self) -> bool {
+        self.line.is_none()
+    }
+
+    fn column(&self, ctx: &mut vtab::Context, col: i32) -> Result<()> {
+        match col {
+            0 => ctx.set_result(&String::from_utf8_lossy(
+                self.line.as_deref().unwrap_or(&[]),
+            )),
+            1 => ctx.set_result(&self.path),
+            2 => ctx.set_result(&self.delimiter),
+            _ => Ok(()),
+        }
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+/// Registers `lines_read`, reading only the files `policy` allows.
+pub(crate) fn register_lines_read(conn: &Connection, policy: Arc<FsPolicy>) -> Result<()> {
+    conn.create_module(
+        "lines_read",
+        eponymous_only_module::<LinesReadTable>(),
+        Some((policy, module_stats(conn, "lines_read", 2))),
+    )
+}
@@ -0,0 +1,33 @@
+use rusqlite::{functions::FunctionFlags, Connection, Result};
+
+use crate::function_stats::create_scalar_function;
+
+/// Version reported by `lines_version()`, kept from the sqlite-lines release this module replaces.
+const LINES_VERSION: &str = "v0.1.0";
+
+pub fn register_meta_functions(conn: &Connection) -> Result<()> {
+    create_scalar_function(
+        conn,
+        "lines_version",
+        0,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |_ctx| Ok(LINES_VERSION),
+    )?;
+
+    create_scalar_function(
+        conn,
+        "lines_debug",
+        0,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |_ctx| {
+            Ok(format!(
+                "Version: {}\nDate: {}\nSource: {}",
+                LINES_VERSION,
+                env!("SURVEILR_BUILD_DATE"),
+                env!("SURVEILR_GIT_SHA")
+            ))
+        },
+    )?;
+
+    Ok(())
+}
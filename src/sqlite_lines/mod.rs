@@ -1,16 +1,55 @@
-use libsqlite3_sys::sqlite3_auto_extension;
+#[cfg(not(feature = "loadable_extension"))]
+use libsqlite3_sys::{sqlite3, sqlite3_api_routines, sqlite3_auto_extension};
+use rusqlite::{Connection, Result};
+#[cfg(not(feature = "loadable_extension"))]
+use std::os::raw::{c_char, c_int};
+use std::sync::Arc;
 
-mod bindings;
+use crate::FsPolicy;
 
+mod lines;
+mod lines_read;
+mod meta;
+
+use lines::register_lines;
+pub(crate) use lines_read::register_lines_read;
+use meta::register_meta_functions;
+
+// a loaded extension has no business installing process-wide auto extensions in its host
+#[cfg(not(feature = "loadable_extension"))]
 pub fn initialize_sqite_lines_extensions() {
     unsafe {
-        sqlite3_auto_extension(Some(bindings::sqlite3_lines_init));
+        sqlite3_auto_extension(Some(sqlite3_lines_init));
     }
 }
 
-#[cfg(test)]
+#[cfg(not(feature = "loadable_extension"))]
+unsafe extern "C" fn sqlite3_lines_init(
+    db: *mut sqlite3,
+    pz_err_msg: *mut *mut c_char,
+    _p_api: *const sqlite3_api_routines,
+) -> c_int {
+    let result =
+        Connection::from_handle(db).and_then(|conn| register_sqlite_lines_extensions(&conn));
+    match result {
+        Ok(()) => rusqlite::ffi::SQLITE_OK,
+        Err(err) => crate::report_error(err, pz_err_msg),
+    }
+}
+
+/// Registers `lines`, `lines_read`, `lines_version` and `lines_debug` on `conn` only.
+///
+/// `lines_read` is direct-only and honours the connection's [`FsPolicy`], unrestricted until
+/// [`apply_fs_policy`](crate::apply_fs_policy) says otherwise.
+pub fn register_sqlite_lines_extensions(conn: &Connection) -> Result<()> {
+    register_meta_functions(conn)?;
+    register_lines(conn)?;
+    register_lines_read(conn, Arc::new(FsPolicy::unrestricted()))
+}
+
+#[cfg(all(test, not(feature = "loadable_extension")))]
 mod tests {
-    use rusqlite::{Connection, Result};
+    use rusqlite::{Connection, Error, Result};
 
     use super::*;
 
@@ -21,7 +60,7 @@ mod tests {
         let mut stmt = conn.prepare("SELECT lines_version()")?;
         let version: String = stmt.query_row([], |row| row.get(0))?;
 
-        let expected_version = format!("v0.1.0");
+        let expected_version = "v0.1.0".to_string();
         assert_eq!(version, expected_version);
         Ok(())
     }
@@ -61,17 +100,21 @@ mod tests {
         assert_eq!(
             rows,
             vec![
-                (1, "\n".to_string(), "".to_string(), "a".to_string()),
-                (2, "\n".to_string(), "".to_string(), "b".to_string()),
+                (1, "\n".to_string(), "a\nb".to_string(), "a".to_string()),
+                (2, "\n".to_string(), "a\nb".to_string(), "b".to_string()),
             ]
         );
 
-        // let err = {
-        //     let mut stmt = conn.prepare("SELECT line FROM lines('axxb', 'xx')")?;
-        //     stmt.query([]).err()
-        // };
-        // println!("{err:#?}");
-        // assert!(matches!(err, Some(Error::SqliteFailure(_, _))));
+        let mut stmt = conn.prepare("SELECT line FROM lines('axxb', 'xx')")?;
+        let lines: Vec<String> = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<_>, _>>()?;
+        assert_eq!(lines, vec!["a".to_string(), "b".to_string()]);
+
+        let err = conn
+            .query_row("SELECT line FROM lines('a', '')", [], |row| row.get::<_, String>(0))
+            .err();
+        assert!(matches!(err, Some(Error::SqliteFailure(_, _))));
 
         Ok(())
     }
@@ -110,12 +153,42 @@ mod tests {
         );
 
 
-        // let err = {
-        //     let mut stmt = conn.prepare("SELECT line FROM lines_read('notexist.txt')")?;
-        //     stmt.query([]).err()
-        // };
-        // assert!(matches!(err, Some(Error::SqliteFailure(_, _))));
+        let err = conn
+            .query_row("SELECT line FROM lines_read('notexist.txt')", [], |row| {
+                row.get::<_, String>(0)
+            })
+            .err();
+        assert!(matches!(err, Some(Error::SqliteFailure(_, _))));
+
+        Ok(())
+    }
 
+    #[test]
+    fn test_lines_read_fs_policy() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        register_sqlite_lines_extensions(&conn)?;
+
+        let test_files =
+            std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("src/sqlite_lines/test_files");
+        let test_file = test_files.join("test.txt");
+        let test_file = test_file.to_str().unwrap();
+        let count_lines = |conn: &Connection| {
+            conn.query_row("SELECT count(*) FROM lines_read(?)", [test_file], |row| {
+                row.get::<_, i64>(0)
+            })
+        };
+
+        crate::apply_fs_policy(&conn, FsPolicy::new().allow_root(std::env::temp_dir()))?;
+        assert!(count_lines(&conn).is_err());
+
+        crate::apply_fs_policy(&conn, FsPolicy::new().allow_root(&test_files))?;
+        assert_eq!(count_lines(&conn)?, 3);
+
+        conn.execute_batch(&format!(
+            "CREATE VIEW v AS SELECT line FROM lines_read('{}')",
+            test_file
+        ))?;
+        assert!(conn.query_row("SELECT count(*) FROM v", [], |_| Ok(())).is_err());
         Ok(())
     }
 }
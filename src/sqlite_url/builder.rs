@@ -0,0 +1,407 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use rusqlite::{functions::FunctionFlags, types::ValueRef, Connection, Result};
+use serde_json::Value;
+use url::{Host, Position, Url};
+
+use super::user_error;
+use crate::function_stats::create_scalar_function;
+
+/// Characters left alone in an IPv6 zone ID, the `unreserved` set of RFC 3986.
+const ZONE_ID: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// Order in which the keys of a JSON object are applied, `path` before `options`.
+const JSON_KEYS: &[&str] = &[
+    "scheme", "user", "password", "host", "port", "zoneid", "path", "options", "query", "fragment",
+];
+
+/// Text of a key or value argument, `None` for NULL.
+fn value_text(value: ValueRef<'_>) -> Option<String> {
+    match value {
+        ValueRef::Null => None,
+        ValueRef::Integer(i) => Some(i.to_string()),
+        ValueRef::Real(f) => Some(f.to_string()),
+        ValueRef::Text(bytes) | ValueRef::Blob(bytes) => {
+            Some(String::from_utf8_lossy(bytes).into_owned())
+        }
+    }
+}
+
+/// Text of a JSON component value, `None` for null.
+fn json_text(key: &str, value: &Value) -> Result<Option<String>> {
+    match value {
+        Value::Null => Ok(None),
+        Value::String(text) => Ok(Some(text.clone())),
+        Value::Number(number) => Ok(Some(number.to_string())),
+        _ => Err(user_error(format!("{} must be a string or a number", key))),
+    }
+}
+
+/// Applies the key/value components given to `url()` on top of a base URL.
+struct UrlBuilder {
+    url: Url,
+    keys: Vec<String>,
+    zone_id: Option<String>,
+}
+
+impl UrlBuilder {
+    /// Starts from `base`, or when there is none from the `scheme` (`https` by default) and
+    /// `host` in `components`.
+    fn new(base: Option<&str>, components: &[(String, Option<String>)]) -> Result<UrlBuilder> {
+        let component = |key: &str| {
+            components
+                .iter()
+                .find(|(name, _)| name == key)
+                .and_then(|(_, value)| value.as_deref())
+        };
+        let url = match base.filter(|base| !base.is_empty()) {
+            Some(base) => {
+                Url::parse(base).map_err(|e| user_error(format!("Invalid base URL: {}", e)))?
+            }
+            None => {
+                let Some(host) = component("host") else {
+                    return Err(user_error("url() without a base URL requires a host"));
+                };
+                let scheme = component("scheme").unwrap_or("https");
+                Url::parse(&format!("{}://{}", scheme, host))
+                    .map_err(|e| user_error(format!("Invalid host: {}", e)))?
+            }
+        };
+        Ok(UrlBuilder {
+            url,
+            keys: vec![],
+            zone_id: None,
+        })
+    }
+
+    fn set(&mut self, key: &str, value: Option<&str>) -> Result<()> {
+        if key == "param" {
+            let (name, value) = value
+                .map(|value| value.split_once('=').unwrap_or((value, "")))
+                .unwrap_or_default();
+            return self.append_param(name, value);
+        }
+        if self.keys.iter().any(|seen| seen == key) {
+            return Err(user_error(format!("Duplicate key: {}", key)));
+        }
+        if key == "query" && self.keys.iter().any(|seen| seen == "param") {
+            return Err(user_error("query and param can't be combined"));
+        }
+        self.keys.push(key.to_string());
+
+        let value = value.unwrap_or("");
+        match key {
+            "scheme" => {
+                self.url
+                    .set_scheme(value)
+                    .map_err(|_| user_error("Invalid scheme"))?;
+            }
+            "host" => {
+                self.url
+                    .set_host(Some(value))
+                    .map_err(|_| user_error("Invalid host"))?;
+            }
+            "port" => {
+                let port = match value {
+                    "" => None,
+                    value => Some(
+                        value
+                            .parse::<u16>()
+                            .map_err(|_| user_error(format!("Invalid port: {}", value)))?,
+                    ),
+                };
+                self.url
+                    .set_port(port)
+                    .map_err(|_| user_error("This URL can't have a port"))?;
+            }
+            "zoneid" => {
+                self.zone_id = Some(value.to_string()).filter(|zone_id| !zone_id.is_empty());
+            }
+            "path" => {
+                self.url.set_path(value);
+            }
+            "query" => {
+                self.url.set_query(Some(value));
+            }
+            "fragment" => {
+                self.url.set_fragment(Some(value));
+            }
+            "user" => {
+                self.url
+                    .set_username(value)
+                    .map_err(|_| user_error("Invalid username"))?;
+            }
+            "password" => {
+                self.url
+                    .set_password(Some(value))
+                    .map_err(|_| user_error("Invalid password"))?;
+            }
+            "options" => {
+                let mut path = self.url.path().to_string();
+                if !path.ends_with(";") {
+                    path.push(';');
+                }
+                path.push_str(value);
+                self.url.set_path(&path);
+            }
+            _ => {
+                return Err(user_error(format!("Unknown key: {}", key)));
+            }
+        }
+        Ok(())
+    }
+
+    /// Appends an encoded `name=value` pair to the query.
+    fn append_param(&mut self, name: &str, value: &str) -> Result<()> {
+        if self.keys.iter().any(|seen| seen == "query") {
+            return Err(user_error("query and param can't be combined"));
+        }
+        if !self.keys.iter().any(|seen| seen == "param") {
+            self.keys.push("param".to_string());
+        }
+        self.url.query_pairs_mut().append_pair(name, value);
+        Ok(())
+    }
+
+    /// Serializes the URL, with the zone ID inserted into its IPv6 host as per RFC 6874: the
+    /// URL parser rejects zone IDs, so they can only be added to the text.
+    fn finish(self) -> Result<String> {
+        let Some(zone_id) = self.zone_id else {
+            return Ok(self.url.to_string());
+        };
+        if !matches!(self.url.host(), Some(Host::Ipv6(_))) {
+            return Err(user_error("zoneid requires an IPv6 host"));
+        }
+        let serialized = self.url.as_str();
+        let bracket = self.url[..Position::AfterHost].len() - 1;
+        Ok(format!(
+            "{}%25{}{}",
+            &serialized[..bracket],
+            utf8_percent_encode(&zone_id, ZONE_ID),
+            &serialized[bracket..]
+        ))
+    }
+
+    /// `url(json)`: the base URL in `url`, the components under their own keys and query pairs
+    /// in `params`, as `[name, value]` arrays or `name=value` strings.
+    fn from_json(json: &str) -> Result<String> {
+        let object = match serde_json::from_str(json) {
+            Ok(Value::Object(object)) => object,
+            Ok(_) => return Err(user_error("url() JSON argument must be an object")),
+            Err(e) => return Err(user_error(format!("Invalid JSON: {}", e))),
+        };
+        if let Some(key) = object.keys().find(|key| {
+            !matches!(key.as_str(), "url" | "params") && !JSON_KEYS.contains(&key.as_str())
+        }) {
+            return Err(user_error(format!("Unknown key: {}", key)));
+        }
+
+        let base = object
+            .get("url")
+            .map(|base| json_text("url", base))
+            .transpose()?
+            .flatten();
+        let mut components = vec![];
+        for &key in JSON_KEYS {
+            if let Some(value) = object.get(key) {
+                components.push((key.to_string(), json_text(key, value)?));
+            }
+        }
+
+        let mut builder = UrlBuilder::new(base.as_deref(), &components)?;
+        for (key, value) in &components {
+            builder.set(key, value.as_deref())?;
+        }
+        match object.get("params") {
+            None | Some(Value::Null) => {}
+            Some(Value::Array(params)) => {
+                for param in params {
+                    match param {
+                        Value::String(param) => builder.set("param", Some(param))?,
+                        Value::Array(pair) if pair.len() == 2 => {
+                            let name = json_text("params", &pair[0])?.unwrap_or_default();
+                            let value = json_text("params", &pair[1])?.unwrap_or_default();
+                            builder.append_param(&name, &value)?;
+                        }
+                        _ => {
+                            return Err(user_error(
+                                "params must hold [name, value] arrays or name=value strings",
+                            ))
+                        }
+                    }
+                }
+            }
+            Some(_) => return Err(user_error("params must be an array")),
+        }
+        builder.finish()
+    }
+}
+
+pub fn register_builder_functions(conn: &Connection) -> Result<()> {
+    create_scalar_function(
+        conn,
+        "url",
+        -1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let args_count = ctx.len();
+            if args_count < 1 {
+                return Err(user_error("url() requires at least one argument"));
+            }
+
+            let base_url = value_text(ctx.get_raw(0));
+            if args_count == 1 {
+                if let Some(json) = base_url
+                    .as_deref()
+                    .filter(|arg| arg.trim_start().starts_with('{'))
+                {
+                    return UrlBuilder::from_json(json);
+                }
+            }
+            if args_count % 2 == 0 {
+                return Err(user_error(
+                    "url() expects a base URL followed by key/value pairs",
+                ));
+            }
+
+            let components: Vec<(String, Option<String>)> = (1..args_count)
+                .step_by(2)
+                .map(|i| {
+                    (
+                        value_text(ctx.get_raw(i)).unwrap_or_default(),
+                        value_text(ctx.get_raw(i + 1)),
+                    )
+                })
+                .collect();
+            let mut builder = UrlBuilder::new(base_url.as_deref(), &components)?;
+            for (key, value) in &components {
+                builder.set(key, value.as_deref())?;
+            }
+            builder.finish()
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_builder_functions(&conn).unwrap();
+        conn
+    }
+
+    fn url(conn: &Connection, sql: &str) -> Result<String> {
+        conn.query_row(sql, [], |row| row.get(0))
+    }
+
+    #[test]
+    fn test_url_function() -> rusqlite::Result<()> {
+        let conn = setup_connection();
+
+        let result: String = conn.query_row(
+            "SELECT url('http://github.com', 'path', 'asg017/sqlite-url', 'fragment', 'usage')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(result, "http://github.com/asg017/sqlite-url#usage");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_port_and_params() -> Result<()> {
+        let conn = setup_connection();
+        assert_eq!(
+            url(
+                &conn,
+                "SELECT url('', 'host', 'example.com', 'port', 8080, 'param', 'q=a b', 'param', 'lang=en')"
+            )?,
+            "https://example.com:8080/?q=a+b&lang=en"
+        );
+        assert_eq!(
+            url(
+                &conn,
+                "SELECT url('http://example.com:8080/', 'port', NULL)"
+            )?,
+            "http://example.com/"
+        );
+        assert_eq!(
+            url(
+                &conn,
+                "SELECT url('', 'scheme', 'ftp', 'host', 'files.example.com')"
+            )?,
+            "ftp://files.example.com/"
+        );
+
+        for sql in [
+            "SELECT url('https://a.com', 'port', 'http')",
+            "SELECT url('https://a.com', 'port', 70000)",
+            "SELECT url('https://a.com', 'host', 'b.com', 'host', 'c.com')",
+            "SELECT url('https://a.com', 'query', 'x=1', 'param', 'y=2')",
+            "SELECT url('https://a.com', 'param', 'y=2', 'query', 'x=1')",
+            "SELECT url('https://a.com', 'path')",
+            "SELECT url('', 'path', '/x')",
+        ] {
+            assert!(url(&conn, sql).is_err(), "{}", sql);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_zoneid() -> Result<()> {
+        let conn = setup_connection();
+        assert_eq!(
+            url(
+                &conn,
+                "SELECT url('', 'scheme', 'http', 'host', '[fe80::1]', 'port', 8080, 'zoneid', 'eth0')"
+            )?,
+            "http://[fe80::1%25eth0]:8080/"
+        );
+        assert_eq!(
+            url(&conn, "SELECT url('http://[fe80::1]/x', 'zoneid', 'en 1')")?,
+            "http://[fe80::1%25en%201]/x"
+        );
+        assert!(url(&conn, "SELECT url('http://example.com', 'zoneid', 'eth0')").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_json() -> Result<()> {
+        let conn = setup_connection();
+        assert_eq!(
+            url(
+                &conn,
+                "SELECT url(json_object('host', 'example.com', 'path', '/search', 'fragment', 'top', 'params', json_array(json_array('q', 'a&b'), 'page=2')))"
+            )?,
+            "https://example.com/search?q=a%26b&page=2#top"
+        );
+        assert_eq!(
+            url(
+                &conn,
+                r#"SELECT url('{"url": "https://example.com/a", "options": "x=1", "path": "/b", "port": 8443}')"#
+            )?,
+            "https://example.com:8443/b;x=1"
+        );
+
+        for json in [
+            r#"{"host": "a.com", "query": "x=1", "params": ["y=2"]}"#,
+            r#"{"host": "a.com", "colour": "red"}"#,
+            r#"{"host": "a.com", "params": {"y": 2}}"#,
+            r#"{"host": "a.com", "port": true}"#,
+            r#"{"host": "a.com""#,
+        ] {
+            assert!(
+                conn.query_row("SELECT url(?)", [json], |_| Ok(())).is_err(),
+                "{}",
+                json
+            );
+        }
+        Ok(())
+    }
+}
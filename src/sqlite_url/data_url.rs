@@ -0,0 +1,131 @@
+use data_url::DataUrl;
+use rusqlite::{functions::Context, functions::FunctionFlags, Connection, Result};
+use serde_json::{Map, Value};
+
+use super::user_error;
+use crate::function_stats::create_scalar_function;
+
+/// Processes the `data:` URL in the first argument as per the Fetch standard, `None` for NULL.
+/// A missing media type defaults to `text/plain;charset=US-ASCII`.
+fn data_url_arg<T>(
+    ctx: &Context<'_>,
+    f: impl FnOnce(&DataUrl<'_>) -> Result<T>,
+) -> Result<Option<T>> {
+    let Some(url_text) = ctx.get::<Option<String>>(0)? else {
+        return Ok(None);
+    };
+    let data_url = DataUrl::process(&url_text).map_err(|err| user_error(format!("{}", err)))?;
+    f(&data_url).map(Some)
+}
+
+pub fn register_data_url_functions(conn: &Connection) -> Result<()> {
+    create_scalar_function(
+        conn,
+        "data_url_mime",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            data_url_arg(ctx, |data_url| {
+                let mime = data_url.mime_type();
+                Ok(format!("{}/{}", mime.type_, mime.subtype))
+            })
+        },
+    )?;
+
+    // a JSON object, the media type parameters are already lowercased and deduplicated
+    create_scalar_function(
+        conn,
+        "data_url_params",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            data_url_arg(ctx, |data_url| {
+                let params: Map<String, Value> = data_url
+                    .mime_type()
+                    .parameters
+                    .iter()
+                    .map(|(name, value)| (name.clone(), Value::String(value.clone())))
+                    .collect();
+                Ok(Value::Object(params).to_string())
+            })
+        },
+    )?;
+
+    create_scalar_function(
+        conn,
+        "data_url_decode",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            data_url_arg(ctx, |data_url| {
+                data_url
+                    .decode_to_vec()
+                    .map(|(body, _fragment)| body)
+                    .map_err(|_| user_error("data_url_decode(): invalid base64 body"))
+            })
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_data_url_functions(&conn).unwrap();
+        conn
+    }
+
+    #[test]
+    fn test_data_url_functions() -> Result<()> {
+        let conn = setup_connection();
+        let row = |url: &str| -> Result<(String, String, Vec<u8>)> {
+            conn.query_row(
+                "SELECT data_url_mime(?1), data_url_params(?1), data_url_decode(?1)",
+                [url],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+        };
+
+        assert_eq!(
+            row("data:image/PNG;base64,iVBORw0K GgoA#frag")?,
+            (
+                "image/png".to_string(),
+                "{}".to_string(),
+                b"\x89PNG\r\n\x1a\n\x00".to_vec()
+            )
+        );
+        assert_eq!(
+            row("data:text/html;Charset=UTF-8,%3Ch1%3EHi%3C/h1%3E")?,
+            (
+                "text/html".to_string(),
+                r#"{"charset":"UTF-8"}"#.to_string(),
+                b"<h1>Hi</h1>".to_vec()
+            )
+        );
+        assert_eq!(
+            row("data:,Hello%2C%20World")?,
+            (
+                "text/plain".to_string(),
+                r#"{"charset":"US-ASCII"}"#.to_string(),
+                b"Hello, World".to_vec()
+            )
+        );
+
+        let null: Option<Vec<u8>> =
+            conn.query_row("SELECT data_url_decode(NULL)", [], |row| row.get(0))?;
+        assert_eq!(null, None);
+        for url in [
+            "https://example.com/",
+            "data:text/plain",
+            "data:;base64,!!!",
+        ] {
+            assert!(row(url).is_err(), "{}", url);
+        }
+        Ok(())
+    }
+}
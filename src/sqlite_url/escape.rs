@@ -1,30 +1,170 @@
-use rusqlite::{Connection, Result, functions::FunctionFlags};
-use percent_encoding::{percent_decode, utf8_percent_encode, NON_ALPHANUMERIC};
+use rusqlite::{Connection, Result, functions::{Context, FunctionFlags}, types::Value};
+use percent_encoding::{percent_decode, utf8_percent_encode, AsciiSet, CONTROLS, NON_ALPHANUMERIC};
+use url::form_urlencoded;
+
+use super::user_error;
+use crate::function_stats::create_scalar_function;
+
+// https://url.spec.whatwg.org/#percent-encoded-bytes, as applied by the url crate
+const FRAGMENT: &AsciiSet = &CONTROLS.add(b' ').add(b'"').add(b'<').add(b'>').add(b'`');
+/// The special-query set, what `http(s)` URLs get.
+const QUERY: &AsciiSet = &CONTROLS
+    .add(b' ')
+    .add(b'"')
+    .add(b'#')
+    .add(b'<')
+    .add(b'>')
+    .add(b'\'');
+const PATH: &AsciiSet = &FRAGMENT.add(b'#').add(b'?').add(b'{').add(b'}');
+const USERINFO: &AsciiSet = &PATH
+    .add(b'/')
+    .add(b':')
+    .add(b';')
+    .add(b'=')
+    .add(b'@')
+    .add(b'[')
+    .add(b'\\')
+    .add(b']')
+    .add(b'^')
+    .add(b'|');
+const COMPONENT: &AsciiSet = &USERINFO
+    .add(b'$')
+    .add(b'%')
+    .add(b'&')
+    .add(b'+')
+    .add(b',');
+
+/// The `mode` argument of `url_escape` and `url_unescape`, the part of a URL the text is for.
+#[derive(Clone, Copy)]
+enum Mode {
+    Component,
+    Path,
+    Query,
+    Fragment,
+    Userinfo,
+    /// `application/x-www-form-urlencoded`, with `+` for spaces.
+    Form,
+}
+
+impl Mode {
+    /// Mode in argument `idx`, `None` when absent or NULL.
+    fn arg(ctx: &Context<'_>, idx: usize) -> Result<Option<Mode>> {
+        if idx >= ctx.len() {
+            return Ok(None);
+        }
+        let Some(name) = ctx.get::<Option<String>>(idx)? else {
+            return Ok(None);
+        };
+        let mode = match name.to_ascii_lowercase().as_str() {
+            "component" => Mode::Component,
+            "path" => Mode::Path,
+            "query" => Mode::Query,
+            "fragment" => Mode::Fragment,
+            "userinfo" => Mode::Userinfo,
+            "form" => Mode::Form,
+            _ => {
+                return Err(user_error(format!(
+                    "unknown mode {:?}, expected component, path, query, fragment, userinfo or form",
+                    name
+                )))
+            }
+        };
+        Ok(Some(mode))
+    }
+
+    fn encode_set(self) -> &'static AsciiSet {
+        match self {
+            Mode::Component | Mode::Form => COMPONENT,
+            Mode::Path => PATH,
+            Mode::Query => QUERY,
+            Mode::Fragment => FRAGMENT,
+            Mode::Userinfo => USERINFO,
+        }
+    }
+}
+
+/// What `url_unescape` does with bytes that don't decode to UTF-8.
+enum InvalidUtf8 {
+    Error,
+    Replace,
+    Blob,
+}
+
+impl InvalidUtf8 {
+    fn arg(ctx: &Context<'_>, idx: usize) -> Result<InvalidUtf8> {
+        if idx >= ctx.len() {
+            return Ok(InvalidUtf8::Error);
+        }
+        match ctx.get::<Option<String>>(idx)?.as_deref() {
+            None | Some("error") => Ok(InvalidUtf8::Error),
+            Some("replace") => Ok(InvalidUtf8::Replace),
+            Some("blob") => Ok(InvalidUtf8::Blob),
+            Some(other) => Err(user_error(format!(
+                "unknown invalid UTF-8 handling {:?}, expected error, replace or blob",
+                other
+            ))),
+        }
+    }
+}
+
+/// Percent-encodes `input` with the set of `mode`, or everything but ASCII letters and digits
+/// without one.
+fn escape(input: &str, mode: Option<Mode>) -> String {
+    match mode {
+        None => utf8_percent_encode(input, NON_ALPHANUMERIC).to_string(),
+        Some(Mode::Form) => form_urlencoded::byte_serialize(input.as_bytes()).collect(),
+        Some(mode) => utf8_percent_encode(input, mode.encode_set()).to_string(),
+    }
+}
+
+/// Percent-decodes `input`, reading `+` as a space in `form` mode.
+fn unescape(input: &str, mode: Option<Mode>) -> Vec<u8> {
+    match mode {
+        Some(Mode::Form) => percent_decode(input.replace('+', " ").as_bytes()).collect(),
+        _ => percent_decode(input.as_bytes()).collect(),
+    }
+}
 
 pub fn register_escape_functions(conn: &Connection) -> Result<()> {
-    conn.create_scalar_function(
-        "url_escape",
-        1,
-        FunctionFlags::SQLITE_DETERMINISTIC,
-        |ctx| {
-            let input: String = ctx.get(0)?;
-            let escaped = utf8_percent_encode(&input, NON_ALPHANUMERIC).to_string();
-            Ok(escaped)
-        },
-    )?;
-
-    conn.create_scalar_function(
-        "url_unescape",
-        1,
-        FunctionFlags::SQLITE_DETERMINISTIC,
-        |ctx| {
-            let input: String = ctx.get(0)?;
-            let unescaped = percent_decode(input.as_bytes())
-                .decode_utf8()
-                .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
-            Ok(unescaped.to_string())
-        },
-    )?;
+    // url_escape(text[, mode])
+    for n_arg in [1, 2] {
+        create_scalar_function(
+            conn,
+            "url_escape",
+            n_arg,
+            FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let input: String = ctx.get(0)?;
+                let escaped = escape(&input, Mode::arg(ctx, 1)?);
+                Ok(escaped)
+            },
+        )?;
+    }
+
+    // url_unescape(text[, mode[, invalid_utf8]]), `invalid_utf8` being `error` (the default),
+    // `replace` for U+FFFD replacement characters or `blob` to return the decoded bytes as is
+    for n_arg in [1, 2, 3] {
+        create_scalar_function(
+            conn,
+            "url_unescape",
+            n_arg,
+            FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let input: String = ctx.get(0)?;
+                let bytes = unescape(&input, Mode::arg(ctx, 1)?);
+                let unescaped = match InvalidUtf8::arg(ctx, 2)? {
+                    InvalidUtf8::Error => std::str::from_utf8(&bytes)
+                        .map(|text| Value::Text(text.to_string()))
+                        .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?,
+                    InvalidUtf8::Replace => {
+                        Value::Text(String::from_utf8_lossy(&bytes).into_owned())
+                    }
+                    InvalidUtf8::Blob => Value::Blob(bytes),
+                };
+                Ok(unescaped)
+            },
+        )?;
+    }
 
     Ok(())
 }
@@ -59,4 +199,39 @@ mod tests {
         let result: String = conn.query_row("SELECT url_unescape('special%40chars%21')", [], |row| row.get(0)).unwrap();
         assert_eq!(result, "special@chars!");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_url_escape_modes() {
+        let conn = setup_connection();
+        let escape = |mode: &str| -> String {
+            conn.query_row("SELECT url_escape(?, ?)", ["a b/c?d=e&f#g+h'é~", mode], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(escape("component"), "a%20b%2Fc%3Fd%3De%26f%23g%2Bh'%C3%A9~");
+        assert_eq!(escape("path"), "a%20b/c%3Fd=e&f%23g+h'%C3%A9~");
+        assert_eq!(escape("query"), "a%20b/c?d=e&f%23g+h%27%C3%A9~");
+        assert_eq!(escape("fragment"), "a%20b/c?d=e&f#g+h'%C3%A9~");
+        assert_eq!(escape("userinfo"), "a%20b%2Fc%3Fd%3De&f%23g+h'%C3%A9~");
+        assert_eq!(escape("FORM"), "a+b%2Fc%3Fd%3De%26f%23g%2Bh%27%C3%A9%7E");
+
+        let legacy: String = conn
+            .query_row("SELECT url_escape('a b~', NULL)", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(legacy, "a%20b%7E");
+        assert!(conn.query_row("SELECT url_escape('a', 'host')", [], |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_url_unescape_modes() {
+        let conn = setup_connection();
+        let unescape = |sql: &str| -> Value { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+
+        assert_eq!(unescape("SELECT url_unescape('a+b%2B', 'form')"), Value::Text("a b+".to_string()));
+        assert_eq!(unescape("SELECT url_unescape('a+b%2B', 'query')"), Value::Text("a+b+".to_string()));
+        assert!(conn.query_row("SELECT url_unescape('%FFok')", [], |_| Ok(())).is_err());
+        assert!(conn.query_row("SELECT url_unescape('%FFok', NULL, 'error')", [], |_| Ok(())).is_err());
+        assert_eq!(unescape("SELECT url_unescape('%FFok', NULL, 'replace')"), Value::Text("\u{FFFD}ok".to_string()));
+        assert_eq!(unescape("SELECT url_unescape('%FF+ok', 'form', 'blob')"), Value::Blob(b"\xFF ok".to_vec()));
+        assert!(conn.query_row("SELECT url_unescape('a', NULL, 'ignore')", [], |_| Ok(())).is_err());
+    }
+}
@@ -0,0 +1,237 @@
+use rusqlite::{types::Value, Connection, Error, Result};
+use std::ops::Range;
+use url::Url;
+
+use super::public_suffix::has_listed_suffix;
+use crate::{register_table_function, Rows, TableFunction};
+
+/// Scheme assumed for `www.` and bare-domain matches.
+const DEFAULT_SCHEME: &str = "http://";
+
+fn is_scheme_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'+' | b'-' | b'.')
+}
+
+fn is_domain_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, b'-' | b'.')
+}
+
+/// Characters that end a URL in running text or an HTML attribute.
+fn is_terminator(c: char) -> bool {
+    c.is_whitespace() || c.is_control() || matches!(c, '<' | '>' | '"' | '\'' | '`')
+}
+
+/// A match may only start after a character that can't be part of a word, an email address or
+/// another URL.
+fn is_boundary(text: &[u8], pos: usize) -> bool {
+    pos == 0 || {
+        let c = text[pos - 1];
+        !(c.is_ascii_alphanumeric() || matches!(c, b'+' | b'-' | b'.' | b'@' | b'_' | b'/'))
+    }
+}
+
+/// End of the URL starting at `start`: up to the next terminator, without trailing punctuation
+/// and unbalanced closing brackets.
+fn url_end(text: &str, start: usize) -> usize {
+    let mut end = text[start..]
+        .find(is_terminator)
+        .map_or(text.len(), |offset| start + offset);
+    while end > start {
+        let last = text[start..end].chars().next_back().unwrap();
+        let unbalanced = |open: char| {
+            text[start..end].matches(open).count() < text[start..end].matches(last).count()
+        };
+        let trim = match last {
+            '.' | ',' | ';' | ':' | '!' | '?' | '*' => true,
+            ')' => unbalanced('('),
+            ']' => unbalanced('['),
+            '}' => unbalanced('{'),
+            _ => false,
+        };
+        if !trim {
+            break;
+        }
+        end -= last.len_utf8();
+    }
+    end
+}
+
+/// Length of the domain at the start of `text`, if it is made of at least two labels and ends
+/// in a suffix from the public suffix list.
+fn bare_domain_len(text: &str) -> Option<usize> {
+    let len = text.bytes().take_while(|&c| is_domain_char(c)).count();
+    let domain = text[..len].trim_end_matches('.');
+    let valid = domain.contains('.')
+        && domain
+            .split('.')
+            .all(|label| !label.is_empty() && !label.starts_with('-') && !label.ends_with('-'))
+        && has_listed_suffix(domain);
+    valid.then_some(domain.len())
+}
+
+/// Finds the URLs in `text`: `scheme://` ones, `www.` hosts and, with `bare_domains`, domain
+/// names under a listed public suffix. Returns the byte range of each match along with the
+/// URL it parses to.
+fn extract_urls(text: &str, bare_domains: bool) -> Vec<(Range<usize>, Url)> {
+    let bytes = text.as_bytes();
+    let mut urls = vec![];
+    let mut pos = 0;
+    while pos < bytes.len() {
+        if !text.is_char_boundary(pos) || !is_boundary(bytes, pos) {
+            pos += 1;
+            continue;
+        }
+
+        let rest = &text[pos..];
+        let scheme_len = bytes[pos..]
+            .iter()
+            .take_while(|&&c| is_scheme_char(c))
+            .count();
+        let bare_domain_len = match bare_domains {
+            true => bare_domain_len(rest),
+            false => None,
+        };
+        let (body_start, prefix) =
+            if bytes[pos].is_ascii_alphabetic() && rest[scheme_len..].starts_with("://") {
+                (pos + scheme_len + 3, "")
+            } else if rest
+                .get(..4)
+                .is_some_and(|www| www.eq_ignore_ascii_case("www."))
+                && bytes.get(pos + 4).is_some_and(u8::is_ascii_alphanumeric)
+            {
+                (pos, DEFAULT_SCHEME)
+            } else if let Some(len) = bare_domain_len {
+                // an email address or a longer word, not a domain
+                if matches!(bytes.get(pos + len), Some(b'@' | b'_')) {
+                    pos += len + 1;
+                    continue;
+                }
+                (pos, DEFAULT_SCHEME)
+            } else {
+                pos += 1;
+                continue;
+            };
+
+        let end = url_end(text, body_start);
+        if end == body_start {
+            pos = end.max(pos + 1);
+            continue;
+        }
+        // `&amp;` is how `&` appears in HTML attributes
+        let candidate = format!("{}{}", prefix, &text[pos..end]).replace("&amp;", "&");
+        match Url::parse(&candidate) {
+            Ok(url) if url.has_host() || prefix.is_empty() => {
+                urls.push((pos..end, url));
+                pos = end;
+            }
+            _ => pos += 1,
+        }
+    }
+    urls
+}
+
+/// `url_extract_each(text, bare_domains)`: one `(raw, url, start, end)` row per URL found in
+/// `text`, with `url` normalized by the URL parser (`http://` added to `www.` and bare-domain
+/// matches) and `start`/`end` the byte offsets of `raw`, `end` exclusive. Bare domains such as
+/// `example.com` are only matched when `bare_domains` is true.
+struct ExtractEach;
+
+impl TableFunction for ExtractEach {
+    const COLUMNS: &'static [&'static str] =
+        &["raw text", "url text", "start integer", "end integer"];
+    const ARGUMENTS: &'static [&'static str] = &["text", "bare_domains"];
+    const FIRST_ROWID: i64 = 0;
+
+    fn rows(&self, args: &[Value]) -> Result<Rows> {
+        let text = match &args[0] {
+            Value::Text(text) => text,
+            Value::Null => return Ok(Box::new(std::iter::empty())),
+            _ => return Err(Error::ModuleError("text must be text.".to_string())),
+        };
+        let bare_domains = match args[1] {
+            Value::Null => false,
+            Value::Integer(flag) => flag != 0,
+            _ => {
+                return Err(Error::ModuleError(
+                    "bare_domains must be a boolean.".to_string(),
+                ))
+            }
+        };
+
+        let rows: Vec<Result<Vec<Value>>> = extract_urls(text, bare_domains)
+            .into_iter()
+            .map(|(range, url)| {
+                Ok(vec![
+                    Value::Text(text[range.clone()].to_string()),
+                    Value::Text(url.into()),
+                    Value::Integer(range.start as i64),
+                    Value::Integer(range.end as i64),
+                ])
+            })
+            .collect();
+        Ok(Box::new(rows.into_iter()))
+    }
+}
+
+pub fn register_extract_each_virtual_table(conn: &Connection) -> Result<()> {
+    register_table_function(conn, "url_extract_each", ExtractEach)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn extracted(text: &str, bare_domains: bool) -> Vec<(&str, String)> {
+        extract_urls(text, bare_domains)
+            .into_iter()
+            .map(|(range, url)| (&text[range], url.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn test_extract_urls() {
+        let text =
+            "See https://example.com/a_(b), (www.Example.org/x?y=1&z=2). Mail me@example.net \
+                    or ftp://files.example.com:21/pub!";
+        assert_eq!(
+            extracted(text, false),
+            vec![
+                (
+                    "https://example.com/a_(b)",
+                    "https://example.com/a_(b)".to_string()
+                ),
+                (
+                    "www.Example.org/x?y=1&z=2",
+                    "http://www.example.org/x?y=1&z=2".to_string()
+                ),
+                (
+                    "ftp://files.example.com:21/pub",
+                    "ftp://files.example.com/pub".to_string()
+                ),
+            ]
+        );
+
+        let html = r#"<a href="https://example.com/?a=1&amp;b=2">docs</a><img src='//cdn.example.com/x.png'>"#;
+        assert_eq!(
+            extracted(html, false),
+            vec![(
+                "https://example.com/?a=1&amp;b=2",
+                "https://example.com/?a=1&b=2".to_string()
+            )]
+        );
+    }
+
+    #[test]
+    fn test_extract_bare_domains() {
+        let text = "Visit example.co.uk/shop, not notes.txt or me@example.com; see github.io.";
+        assert_eq!(
+            extracted(text, true),
+            vec![(
+                "example.co.uk/shop",
+                "http://example.co.uk/shop".to_string()
+            ),]
+        );
+        assert!(extracted(text, false).is_empty());
+        assert!(extracted("no links, just https:// and www.", true).is_empty());
+    }
+}
@@ -1,168 +1,101 @@
-use rusqlite::{functions::FunctionFlags, Connection, Error, Result};
+use rusqlite::{functions::FunctionFlags, types::ValueRef, Connection, Result, ToSql};
 use url::Url;
 
-use super::UserError;
-
-pub fn register_extraction_functions(conn: &Connection) -> Result<()> {
-    conn.create_scalar_function(
-        "url",
-        -1,
-        rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC,
-        move |ctx| {
-            let args_count = ctx.len();
-            if args_count < 1 {
-                return Err(Error::UserFunctionError(Box::new(UserError(
-                    "url() requires at least one argument".to_string(),
-                ))));
-            }
-
-            let base_url = ctx.get_raw(0).as_str().unwrap_or("").to_string();
-            let mut url = if !base_url.is_empty() {
-                Url::parse(&base_url).map_err(|e| {
-                    Error::UserFunctionError(Box::new(UserError(format!(
-                        "Invalid base URL: {}",
-                        e
-                    ))))
-                })?
-            } else {
-                Url::parse("https://").unwrap()
-            };
-
-            for i in (1..args_count).step_by(2) {
-                let key = ctx.get_raw(i).as_str().unwrap_or("");
-                let value = ctx.get_raw(i + 1).as_str().unwrap_or("");
-                match key {
-                    "scheme" => {
-                        url.set_scheme(value).map_err(|_| {
-                            Error::UserFunctionError(Box::new(UserError(
-                                "Invalid scheme".to_string(),
-                            )))
-                        })?;
-                    }
-                    "host" => {
-                        url.set_host(Some(value)).map_err(|_| {
-                            Error::UserFunctionError(Box::new(UserError(
-                                "Invalid host".to_string(),
-                            )))
-                        })?;
-                    }
-                    "path" => {
-                        url.set_path(value);
-                    }
-                    "query" => {
-                        url.set_query(Some(value));
-                    }
-                    "fragment" => {
-                        url.set_fragment(Some(value));
-                    }
-                    "user" => {
-                        url.set_username(value).map_err(|_| {
-                            Error::UserFunctionError(Box::new(UserError(
-                                "Invalid username".to_string(),
-                            )))
-                        })?;
-                    }
-                    "password" => {
-                        url.set_password(Some(value)).map_err(|_| {
-                            Error::UserFunctionError(Box::new(UserError(
-                                "Invalid password".to_string(),
-                            )))
-                        })?;
-                    }
-                    "options" => {
-                        let mut path = url.path().to_string();
-                        if !path.ends_with(";") {
-                            path.push(';');
-                        }
-                        path.push_str(value);
-                        url.set_path(&path);
-                    }
-                    // TODO
-                    "zoneid" => {}
-                    _ => {
-                        return Err(Error::UserFunctionError(Box::new(UserError(format!(
-                            "Unknown key: {}",
-                            key
-                        )))));
-                    }
-                }
-            }
-
-            Ok(url.to_string())
-        },
-    )?;
-    conn.create_scalar_function(
-        "url_valid",
-        1,
-        rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC,
-        move |ctx| {
-            let url = ctx.get_raw(0).as_str().unwrap_or("");
-            let is_valid = Url::parse(url).is_ok();
-            Ok(is_valid as i32)
-        },
-    )?;
-
-    conn.create_scalar_function("url_host", 1, FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
-        let url_text: String = ctx.get(0)?;
-        let parsed_url =
-            Url::parse(&url_text).map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
-        Ok(parsed_url.host_str().unwrap_or("").to_string())
-    })?;
-
-    conn.create_scalar_function("url_path", 1, FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
-        let url_text: String = ctx.get(0)?;
-        let parsed_url =
-            Url::parse(&url_text).map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
-        Ok(parsed_url.path().to_string())
-    })?;
-
-    conn.create_scalar_function(
-        "url_scheme",
+use crate::function_stats::create_scalar_function;
+
+type Extractor = fn(&Url) -> String;
+
+/// Text component extractors, registered with [`register_extractor`].
+const EXTRACTORS: &[(&str, Extractor)] = &[
+    ("url_host", |url| url.host_str().unwrap_or("").to_string()),
+    ("url_path", |url| url.path().to_string()),
+    ("url_scheme", |url| url.scheme().to_string()),
+    ("url_query", |url| url.query().unwrap_or("").to_string()),
+    ("url_fragment", |url| {
+        url.fragment().unwrap_or("").to_string()
+    }),
+    ("url_user", |url| url.username().to_string()),
+    ("url_password", |url| {
+        url.password().unwrap_or("").to_string()
+    }),
+];
+
+/// Registers `extract` as `name(url)`, which fails on an unparseable URL, and as the
+/// `url_try_` variant of `name`, which returns NULL instead.
+pub(super) fn register_extractor<T>(
+    conn: &Connection,
+    name: &str,
+    extract: fn(&Url) -> T,
+) -> Result<()>
+where
+    T: ToSql + Send + 'static,
+{
+    create_scalar_function(
+        conn,
+        name,
         1,
         FunctionFlags::SQLITE_DETERMINISTIC,
-        |ctx| {
+        move |ctx| {
             let url_text: String = ctx.get(0)?;
             let parsed_url = Url::parse(&url_text)
                 .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
-            Ok(parsed_url.scheme().to_string())
+            Ok(extract(&parsed_url))
         },
     )?;
 
-    conn.create_scalar_function("url_query", 1, FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
-        let url_text: String = ctx.get(0)?;
-        let parsed_url =
-            Url::parse(&url_text).map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
-        Ok(parsed_url.query().unwrap_or("").to_string())
-    })?;
-
-    conn.create_scalar_function(
-        "url_fragment",
+    // url_try_* return NULL instead of failing the whole statement on one bad row
+    let try_name = name.replacen("url_", "url_try_", 1);
+    create_scalar_function(
+        conn,
+        try_name.as_str(),
         1,
         FunctionFlags::SQLITE_DETERMINISTIC,
-        |ctx| {
-            let url_text: String = ctx.get(0)?;
-            let parsed_url = Url::parse(&url_text)
-                .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
-            Ok(parsed_url.fragment().unwrap_or("").to_string())
+        move |ctx| {
+            let url_text = ctx.get_raw(0).as_str().ok();
+            Ok(url_text
+                .and_then(|url_text| Url::parse(url_text).ok())
+                .map(|parsed_url| extract(&parsed_url)))
+        },
+    )
+}
+
+pub fn register_extraction_functions(conn: &Connection) -> Result<()> {
+    create_scalar_function(
+        conn,
+        "url_valid",
+        1,
+        rusqlite::functions::FunctionFlags::SQLITE_DETERMINISTIC,
+        move |ctx| {
+            let url = ctx.get_raw(0).as_str().unwrap_or("");
+            let is_valid = Url::parse(url).is_ok();
+            Ok(is_valid as i32)
         },
     )?;
 
-    conn.create_scalar_function("url_user", 1, FunctionFlags::SQLITE_DETERMINISTIC, |ctx| {
-        let url_text: String = ctx.get(0)?;
-        let parsed_url =
-            Url::parse(&url_text).map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
-        Ok(parsed_url.username().to_string())
+    for &(name, extract) in EXTRACTORS {
+        register_extractor(conn, name, extract)?;
+    }
+    register_extractor(conn, "url_port", |url| url.port())?;
+    register_extractor(conn, "url_port_or_known_default", |url| {
+        url.port_or_known_default()
     })?;
+    // "null" for opaque origins, like `URL.origin` in browsers
+    register_extractor(conn, "url_origin", |url| url.origin().ascii_serialization())?;
+    register_extractor(conn, "url_authority", |url| url.authority().to_string())?;
 
-    conn.create_scalar_function(
-        "url_password",
+    create_scalar_function(
+        conn,
+        "url_parse_error",
         1,
         FunctionFlags::SQLITE_DETERMINISTIC,
         |ctx| {
-            let url_text: String = ctx.get(0)?;
-            let parsed_url = Url::parse(&url_text)
-                .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
-            Ok(parsed_url.password().unwrap_or("").to_string())
+            let url_text = match ctx.get_raw(0) {
+                ValueRef::Null => return Ok(None),
+                ValueRef::Text(text) => String::from_utf8_lossy(text),
+                // blobs and numbers
+                _ => return Ok(Some("NotText".to_string())),
+            };
+            Ok(Url::parse(&url_text).err().map(|err| format!("{:?}", err)))
         },
     )?;
 
@@ -180,20 +113,6 @@ mod tests {
         conn
     }
 
-    #[test]
-    fn test_url_function() -> rusqlite::Result<()> {
-        let conn = setup_connection();
-
-        let result: String = conn.query_row(
-            "SELECT url('http://github.com', 'path', 'asg017/sqlite-url', 'fragment', 'usage')",
-            [],
-            |row| row.get(0),
-        )?;
-        assert_eq!(result, "http://github.com/asg017/sqlite-url#usage");
-
-        Ok(())
-    }
-
     #[test]
     fn test_url_valid_function() -> rusqlite::Result<()> {
         let conn = setup_connection();
@@ -340,4 +259,118 @@ mod tests {
             .unwrap();
         assert_eq!(result, "");
     }
+
+    #[test]
+    fn test_url_try_extractors() {
+        let conn = setup_connection();
+        let result: Option<String> = conn
+            .query_row(
+                "SELECT url_try_host('https://example.com/path')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(result.as_deref(), Some("example.com"));
+
+        for query in [
+            "SELECT url_try_host('invalid')",
+            "SELECT url_try_path('http://[::1')",
+            "SELECT url_try_scheme(NULL)",
+            "SELECT url_try_query(42)",
+        ] {
+            let result: Option<String> = conn.query_row(query, [], |row| row.get(0)).unwrap();
+            assert_eq!(result, None, "{}", query);
+        }
+
+        let count: i64 = conn
+            .query_row(
+                "SELECT count(url_try_host(value)) FROM (SELECT 'https://a.com' AS value UNION ALL SELECT 'bad' UNION ALL SELECT 'https://b.com')",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(count, 2);
+    }
+
+    #[test]
+    fn test_url_parse_error() {
+        let conn = setup_connection();
+        let error = |url: &str| -> Option<String> {
+            conn.query_row("SELECT url_parse_error(?)", [url], |row| row.get(0))
+                .unwrap()
+        };
+        assert_eq!(error("https://example.com"), None);
+        assert_eq!(error("invalid").as_deref(), Some("RelativeUrlWithoutBase"));
+        assert_eq!(error("http://[::1").as_deref(), Some("InvalidIpv6Address"));
+        assert_eq!(error("http://").as_deref(), Some("EmptyHost"));
+
+        let non_text =
+            |sql: &str| -> Option<String> { conn.query_row(sql, [], |row| row.get(0)).unwrap() };
+        assert_eq!(non_text("SELECT url_parse_error(NULL)"), None);
+        assert_eq!(
+            non_text("SELECT url_parse_error(42)").as_deref(),
+            Some("NotText")
+        );
+        assert_eq!(
+            non_text("SELECT url_parse_error(x'00')").as_deref(),
+            Some("NotText")
+        );
+    }
+
+    #[test]
+    fn test_url_port() {
+        let conn = setup_connection();
+        let port =
+            |query: &str| -> Option<i64> { conn.query_row(query, [], |row| row.get(0)).unwrap() };
+        assert_eq!(
+            port("SELECT url_port('https://example.com:8443/')"),
+            Some(8443)
+        );
+        assert_eq!(port("SELECT url_port('https://example.com:443/')"), None);
+        assert_eq!(port("SELECT url_port('https://example.com/')"), None);
+        assert_eq!(
+            port("SELECT url_port_or_known_default('https://example.com/')"),
+            Some(443)
+        );
+        assert_eq!(
+            port("SELECT url_port_or_known_default('myapp://example.com/')"),
+            None
+        );
+        assert_eq!(port("SELECT url_try_port('invalid')"), None);
+        assert!(conn
+            .query_row("SELECT url_port('invalid')", [], |_| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_url_origin_and_authority() {
+        let conn = setup_connection();
+        let text = |query: &str| -> Option<String> {
+            conn.query_row(query, [], |row| row.get(0)).unwrap()
+        };
+        assert_eq!(
+            text("SELECT url_origin('HTTPS://user:pw@Example.com:8443/a?b#c')").as_deref(),
+            Some("https://example.com:8443")
+        );
+        assert_eq!(
+            text("SELECT url_origin('http://example.com:80/')").as_deref(),
+            Some("http://example.com")
+        );
+        assert_eq!(
+            text("SELECT url_origin('data:text/plain,hi')").as_deref(),
+            Some("null")
+        );
+        assert_eq!(
+            text("SELECT url_authority('https://user:pw@example.com:8443/a')").as_deref(),
+            Some("user:pw@example.com:8443")
+        );
+        assert_eq!(
+            text("SELECT url_authority('mailto:someone@example.com')").as_deref(),
+            Some("")
+        );
+        assert_eq!(text("SELECT url_try_origin('invalid')"), None);
+        assert!(conn
+            .query_row("SELECT url_origin('invalid')", [], |_| Ok(()))
+            .is_err());
+    }
 }
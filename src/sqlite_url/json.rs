@@ -0,0 +1,280 @@
+use percent_encoding::percent_decode_str;
+use rusqlite::{functions::FunctionFlags, Connection, Error, Result};
+use serde_json::{json, Map, Value};
+use url::Url;
+
+use super::user_error;
+use crate::function_stats::create_scalar_function;
+
+/// Keys of `url_parse` objects that are derived from the components and ignored by
+/// `url_from_json`.
+const DERIVED_KEYS: &[&str] = &["href", "origin", "path_segments", "query_pairs"];
+
+/// Components of `url` as a JSON object. Components are serialized (percent-encoded) as in the
+/// URL, `null` when absent; `path_segments` and `query_pairs` are decoded.
+fn url_to_json(url: &Url) -> Value {
+    let path_segments: Vec<String> = url
+        .path_segments()
+        .into_iter()
+        .flatten()
+        .map(|segment| percent_decode_str(segment).decode_utf8_lossy().into_owned())
+        .collect();
+    let query_pairs: Vec<[String; 2]> = url
+        .query_pairs()
+        .map(|(name, value)| [name.into_owned(), value.into_owned()])
+        .collect();
+    json!({
+        "href": url.as_str(),
+        "scheme": url.scheme(),
+        // an empty user is only reported along with a password, as in `https://:pw@host`
+        "user": Some(url.username()).filter(|user| !user.is_empty() || url.password().is_some()),
+        "password": url.password(),
+        "host": url.host_str(),
+        "port": url.port(),
+        "origin": url.origin().ascii_serialization(),
+        "path": url.path(),
+        "path_segments": path_segments,
+        "query": url.query(),
+        "query_pairs": query_pairs,
+        "fragment": url.fragment(),
+    })
+}
+
+/// Component `key` of `object` as text, `None` when missing or null.
+fn component(object: &Map<String, Value>, key: &str) -> Result<Option<String>> {
+    match object.get(key) {
+        None | Some(Value::Null) => Ok(None),
+        Some(Value::String(text)) => Ok(Some(text.clone())),
+        Some(Value::Number(number)) if key == "port" => Ok(Some(number.to_string())),
+        Some(_) => Err(user_error(format!(
+            "url_from_json(): {} must be a string",
+            key
+        ))),
+    }
+}
+
+/// Rebuilds a URL from the components of a `url_parse` object; see [`url_to_json`]. `user`,
+/// `password` and `port` need a host, `password` a user, and with a host the path, if any, must
+/// start with `/`.
+fn url_from_json(json: &str) -> Result<String> {
+    let object = match serde_json::from_str(json) {
+        Ok(Value::Object(object)) => object,
+        Ok(_) => return Err(user_error("url_from_json() requires a JSON object")),
+        Err(e) => return Err(user_error(format!("Invalid JSON: {}", e))),
+    };
+    const COMPONENTS: &[&str] = &[
+        "scheme", "user", "password", "host", "port", "path", "query", "fragment",
+    ];
+    if let Some(key) = object
+        .keys()
+        .find(|key| !COMPONENTS.contains(&key.as_str()) && !DERIVED_KEYS.contains(&key.as_str()))
+    {
+        return Err(user_error(format!("Unknown key: {}", key)));
+    }
+
+    let Some(scheme) = component(&object, "scheme")? else {
+        return Err(user_error("url_from_json() requires a scheme"));
+    };
+    let host = component(&object, "host")?;
+    let user = component(&object, "user")?;
+    let password = component(&object, "password")?;
+    let port = component(&object, "port")?;
+    let path = component(&object, "path")?.unwrap_or_default();
+    // components that can't be written are rejected rather than dropped
+    if host.is_none() {
+        for (key, value) in [("user", &user), ("password", &password), ("port", &port)] {
+            if value.is_some() {
+                return Err(user_error(format!("{} requires a host", key)));
+            }
+        }
+    }
+    if password.is_some() && user.is_none() {
+        return Err(user_error("password requires a user"));
+    }
+    // after a host the path would otherwise run into it
+    if host.is_some() && !path.is_empty() && !path.starts_with('/') {
+        return Err(user_error(format!(
+            "path must start with / when there is a host: {:?}",
+            path
+        )));
+    }
+
+    let mut text = format!("{}:", scheme);
+    if let Some(host) = host {
+        text.push_str("//");
+        if let Some(user) = user {
+            text.push_str(&user);
+            if let Some(password) = password {
+                text.push(':');
+                text.push_str(&password);
+            }
+            text.push('@');
+        }
+        text.push_str(&host);
+        if let Some(port) = port {
+            text.push(':');
+            text.push_str(&port);
+        }
+    }
+    text.push_str(&path);
+    if let Some(query) = component(&object, "query")? {
+        text.push('?');
+        text.push_str(&query);
+    }
+    if let Some(fragment) = component(&object, "fragment")? {
+        text.push('#');
+        text.push_str(&fragment);
+    }
+
+    Url::parse(&text)
+        .map(String::from)
+        .map_err(|err| Error::UserFunctionError(err.into()))
+}
+
+pub fn register_json_functions(conn: &Connection) -> Result<()> {
+    create_scalar_function(
+        conn,
+        "url_parse",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(url_text) = ctx.get::<Option<String>>(0)? else {
+                return Ok(None);
+            };
+            let url = Url::parse(&url_text).map_err(|err| Error::UserFunctionError(err.into()))?;
+            Ok(Some(url_to_json(&url).to_string()))
+        },
+    )?;
+
+    create_scalar_function(
+        conn,
+        "url_from_json",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(json) = ctx.get::<Option<String>>(0)? else {
+                return Ok(None);
+            };
+            url_from_json(&json).map(Some)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_json_functions(&conn).unwrap();
+        conn
+    }
+
+    fn parsed(conn: &Connection, url: &str) -> Value {
+        let json: String = conn
+            .query_row("SELECT url_parse(?)", [url], |row| row.get(0))
+            .unwrap();
+        serde_json::from_str(&json).unwrap()
+    }
+
+    #[test]
+    fn test_url_parse() {
+        let conn = setup_connection();
+        assert_eq!(
+            parsed(
+                &conn,
+                "HTTPS://user:pw@Example.com:8443/a/my%20file?q=a+b&x=%26#top"
+            ),
+            json!({
+                "href": "https://user:pw@example.com:8443/a/my%20file?q=a+b&x=%26#top",
+                "scheme": "https",
+                "user": "user",
+                "password": "pw",
+                "host": "example.com",
+                "port": 8443,
+                "origin": "https://example.com:8443",
+                "path": "/a/my%20file",
+                "path_segments": ["a", "my file"],
+                "query": "q=a+b&x=%26",
+                "query_pairs": [["q", "a b"], ["x", "&"]],
+                "fragment": "top",
+            })
+        );
+
+        let mailto = parsed(&conn, "mailto:someone@example.com");
+        assert_eq!(mailto["host"], Value::Null);
+        assert_eq!(mailto["origin"], "null");
+        assert_eq!(mailto["path_segments"], json!([]));
+
+        let null: Option<String> = conn
+            .query_row("SELECT url_parse(NULL)", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(null, None);
+        assert!(conn
+            .query_row("SELECT url_parse('invalid')", [], |_| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_url_from_json() -> Result<()> {
+        let conn = setup_connection();
+        for url in [
+            "https://user:pw@example.com:8443/a/my%20file?q=a+b&x=%26#top",
+            "https://example.com/",
+            "https://:pw@example.com/",
+            "http://[::1]:8080/x?",
+            "file:///etc/hosts",
+            "mailto:someone@example.com",
+            "data:text/plain,hello#frag",
+        ] {
+            let rebuilt: String =
+                conn.query_row("SELECT url_from_json(url_parse(?))", [url], |row| {
+                    row.get(0)
+                })?;
+            assert_eq!(rebuilt, url);
+        }
+
+        let edited: String = conn.query_row(
+            "SELECT url_from_json(json_set(url_parse('https://example.com/a?b=1'), '$.host', 'example.org', '$.port', 8080, '$.query', NULL))",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(edited, "https://example.org:8080/a");
+        assert!(conn
+            .query_row(
+                "SELECT url_from_json(json_set(url_parse('https://a.com/x'), '$.path', 'y'))",
+                [],
+                |_| Ok(())
+            )
+            .is_err());
+        let hostless: String = conn.query_row(
+            "SELECT url_from_json(json_set(url_parse('mailto:a@b.com'), '$.path', 'c@d.com'))",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(hostless, "mailto:c@d.com");
+
+        for json in [
+            r#"{"host": "example.com"}"#,
+            r#"{"scheme": "https", "host": "example.com", "colour": "red"}"#,
+            r#"{"scheme": "https", "host": "example.com", "path": 1}"#,
+            r#"{"scheme": "https", "host": "example.com", "port": "http"}"#,
+            r#"{"scheme": "https", "host": "a.com", "password": "pw"}"#,
+            r#"{"scheme": "https", "user": "u", "path": "/"}"#,
+            r#"{"scheme": "https", "user": "u", "password": "pw", "path": "/"}"#,
+            r#"{"scheme": "https", "port": 8080, "path": "/"}"#,
+            "[]",
+        ] {
+            assert!(
+                conn.query_row("SELECT url_from_json(?)", [json], |_| Ok(()))
+                    .is_err(),
+                "{}",
+                json
+            );
+        }
+        Ok(())
+    }
+}
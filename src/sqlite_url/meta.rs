@@ -1,14 +1,18 @@
 use rusqlite::{functions::FunctionFlags, Connection, Result};
 
+use crate::function_stats::create_scalar_function;
+
 pub fn register_meta_functions(conn: &Connection) -> Result<()> {
-    conn.create_scalar_function(
+    create_scalar_function(
+        conn,
         "url_version",
         0,
         FunctionFlags::SQLITE_DETERMINISTIC,
         |_ctx| Ok(env!("CARGO_PKG_VERSION")),
     )?;
 
-    conn.create_scalar_function(
+    create_scalar_function(
+        conn,
         "url_debug",
         0,
         FunctionFlags::SQLITE_DETERMINISTIC,
@@ -16,7 +20,7 @@ pub fn register_meta_functions(conn: &Connection) -> Result<()> {
             Ok(format!(
                 "Version: v{}\nDate: {}\nSource: {}",
                 env!("CARGO_PKG_VERSION"),
-                chrono::Utc::now().to_rfc3339(),
+                env!("SURVEILR_BUILD_DATE"),
                 "https://github.com/surveilr/surveilr-extensions"
             ))
         },
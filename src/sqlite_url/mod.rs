@@ -1,14 +1,40 @@
 use rusqlite::{Connection, Result};
 
+mod builder;
+mod data_url;
 mod escape;
+mod extract_each;
 mod extraction;
+mod json;
 mod meta;
+mod normalize;
+mod path_segments;
+mod pattern;
+mod public_suffix;
 mod query_each;
+mod query_params;
+mod resolve;
+mod template;
+mod unicode_host;
+mod url_each;
 
+use builder::register_builder_functions;
+use data_url::register_data_url_functions;
 use escape::register_escape_functions;
+use extract_each::register_extract_each_virtual_table;
 use extraction::register_extraction_functions;
+use json::register_json_functions;
 use meta::register_meta_functions;
-use query_each::register_query_each_virtual_table;
+use normalize::register_normalize_functions;
+use path_segments::register_path_segments_virtual_table;
+use pattern::register_pattern_functions;
+use public_suffix::register_public_suffix_functions;
+use query_each::register_query_each_functions;
+use query_params::register_query_param_functions;
+use resolve::register_resolve_functions;
+use template::register_template_functions;
+use unicode_host::register_unicode_host_functions;
+use url_each::register_url_each_virtual_table;
 
 #[derive(Debug)]
 struct UserError(String);
@@ -21,11 +47,28 @@ impl std::fmt::Display for UserError {
 
 impl std::error::Error for UserError {}
 
+fn user_error(message: impl Into<String>) -> rusqlite::Error {
+    rusqlite::Error::UserFunctionError(Box::new(UserError(message.into())))
+}
+
 pub fn register_sqlite_url_functions(conn: &Connection) -> Result<()> {
     register_meta_functions(conn)?;
+    register_builder_functions(conn)?;
     register_extraction_functions(conn)?;
     register_escape_functions(conn)?;
-    register_query_each_virtual_table(conn)?;
+    register_query_each_functions(conn)?;
+    register_url_each_virtual_table(conn)?;
+    register_path_segments_virtual_table(conn)?;
+    register_extract_each_virtual_table(conn)?;
+    register_query_param_functions(conn)?;
+    register_normalize_functions(conn)?;
+    register_public_suffix_functions(conn)?;
+    register_unicode_host_functions(conn)?;
+    register_resolve_functions(conn)?;
+    register_json_functions(conn)?;
+    register_data_url_functions(conn)?;
+    register_template_functions(conn)?;
+    register_pattern_functions(conn)?;
     Ok(())
 }
 
@@ -61,14 +104,14 @@ mod tests {
         query: &str,
         param: &str,
     ) -> Result<String> {
-        conn.query_row(query, &[&param], |row| row.get(0))
+        conn.query_row(query, [&param], |row| row.get(0))
     }
 
     #[test]
     fn test_url_version() -> Result<()> {
         let conn = connect()?;
 
-        let expected_version = format!("{}", env!("CARGO_PKG_VERSION").trim());
+        let expected_version = env!("CARGO_PKG_VERSION").trim().to_string();
         let version = execute_single_query(&conn, "SELECT url_version()")?;
 
         assert_eq!(version, expected_version);
@@ -156,7 +199,7 @@ mod tests {
         let query_each = |query: &str| -> Vec<(i64, String, String)> {
             conn.prepare("SELECT rowid, name, value FROM url_query_each(?)")
                 .unwrap()
-                .query_map(&[query], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .query_map([query], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
                 .unwrap()
                 .filter_map(Result::ok)
                 .collect()
@@ -166,10 +209,302 @@ mod tests {
         assert_eq!(
             results,
             vec![
-                (0, "a".to_string(), "b".to_string()),
-                (1, "c".to_string(), "d".to_string())
+                (1, "a".to_string(), "b".to_string()),
+                (2, "c".to_string(), "d".to_string())
+            ]
+        );
+        // a colon without `//` is part of a name, not a scheme
+        assert_eq!(
+            query_each("ns:key=value&b=2"),
+            vec![
+                (1, "ns:key".to_string(), "value".to_string()),
+                (2, "b".to_string(), "2".to_string())
+            ]
+        );
+
+        type Row = (
+            String,
+            String,
+            String,
+            Option<String>,
+            i64,
+            i64,
+            i64,
+            String,
+        );
+        let query_each_full = |input: &str, fragment: bool| -> Vec<Row> {
+            conn.prepare(
+                "SELECT name, value, raw_name, raw_value, start, \"end\", occurrence, component
+                 FROM url_query_each(?, ?)",
+            )
+            .unwrap()
+            .query_map(params![input, fragment], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                    row.get(7)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap()
+        };
+        let row = |name: &str,
+                   value: &str,
+                   raw_name: &str,
+                   raw_value: Option<&str>,
+                   start: i64,
+                   end: i64,
+                   occurrence: i64,
+                   component: &str| {
+            (
+                name.to_string(),
+                value.to_string(),
+                raw_name.to_string(),
+                raw_value.map(str::to_string),
+                start,
+                end,
+                occurrence,
+                component.to_string(),
+            )
+        };
+
+        let url = "https://example.com/search?q=a+b%26c&tag=x&&tag=y&flag#access_token=t%3D1";
+        assert_eq!(
+            query_each_full(url, true),
+            vec![
+                row("q", "a b&c", "q", Some("a+b%26c"), 27, 36, 0, "query"),
+                row("tag", "x", "tag", Some("x"), 37, 42, 0, "query"),
+                row("tag", "y", "tag", Some("y"), 44, 49, 1, "query"),
+                row("flag", "", "flag", None, 50, 54, 0, "query"),
+                row(
+                    "access_token",
+                    "t=1",
+                    "access_token",
+                    Some("t%3D1"),
+                    55,
+                    73,
+                    0,
+                    "fragment"
+                ),
             ]
         );
+        assert_eq!(&url[27..36], "q=a+b%26c");
+        assert_eq!(query_each_full(url, false).len(), 4);
+        assert_eq!(
+            query_each_full("?a=1", false),
+            vec![row("a", "1", "a", Some("1"), 1, 4, 0, "query")]
+        );
+        assert!(query_each_full("https://example.com/#a=1", false).is_empty());
+
+        let hidden: i64 = conn.query_row(
+            "SELECT count(*) FROM url_query_each WHERE query = ? AND fragment = 1",
+            [url],
+            |row| row.get(0),
+        )?;
+        assert_eq!(hidden, 5);
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_query_build() -> Result<()> {
+        let conn = connect()?;
+        let query: String = conn.query_row(
+            "SELECT url_query_build(column1, column2) FROM (VALUES
+                ('q', 'a b&c'), (NULL, 'skipped'), ('flag', NULL), ('é', '='))",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(query, "q=a+b%26c&flag&%C3%A9=%3D");
+
+        // pairs round-trip through url_query_each, here without the tracking parameters
+        let rebuilt: String = conn.query_row(
+            "SELECT url_query_build(name, value ORDER BY rowid)
+             FROM url_query_each('https://example.com/?b=2&utm_source=x&a=%201')
+             WHERE name NOT LIKE 'utm\\_%' ESCAPE '\\'",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(rebuilt, "b=2&a=+1");
+
+        let empty: String = conn.query_row(
+            "SELECT url_query_build(name, value) FROM url_query_each('')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(empty, "");
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_each() -> Result<()> {
+        let conn = connect()?;
+
+        let url_each = |url: &str| -> Vec<(String, String, i64, i64)> {
+            conn.prepare("SELECT name, value, start, \"end\" FROM url_each(?)")
+                .unwrap()
+                .query_map([url], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect()
+        };
+        let row = |name: &str, value: &str, start: i64, end: i64| {
+            (name.to_string(), value.to_string(), start, end)
+        };
+
+        assert_eq!(
+            url_each("HTTPS://user:pw@Example.com:8080/a/b?x=1#top"),
+            vec![
+                row("scheme", "HTTPS", 0, 5),
+                row("user", "user", 8, 12),
+                row("password", "pw", 13, 15),
+                row("host", "Example.com", 16, 27),
+                row("port", "8080", 28, 32),
+                row("path", "/a/b", 32, 36),
+                row("query", "x=1", 37, 40),
+                row("fragment", "top", 41, 44),
+            ]
+        );
+        assert_eq!(
+            url_each("http://[::1]:80/é?"),
+            vec![
+                row("scheme", "http", 0, 4),
+                row("host", "[::1]", 7, 12),
+                row("port", "80", 13, 15),
+                row("path", "/é", 15, 17),
+                row("query", "", 18, 18),
+            ]
+        );
+        assert_eq!(
+            url_each("mailto:someone@example.com"),
+            vec![
+                row("scheme", "mailto", 0, 6),
+                row("path", "someone@example.com", 7, 26),
+            ]
+        );
+
+        let substr_matches: bool = conn.query_row(
+            "SELECT min(substr(?1, start + 1, \"end\" - start) = value) FROM url_each(?1)",
+            ["  https://exämple.com/ä?ö#ü"],
+            |row| row.get(0),
+        )?;
+        assert!(substr_matches);
+        assert_eq!(
+            url_each("https:\\\\a.com\\x?y"),
+            vec![
+                row("scheme", "https", 0, 5),
+                row("host", "a.com", 8, 13),
+                row("path", "\\x", 13, 15),
+                row("query", "y", 16, 17),
+            ]
+        );
+        assert_eq!(
+            url_each("file:///etc/hosts"),
+            vec![
+                row("scheme", "file", 0, 4),
+                row("host", "", 7, 7),
+                row("path", "/etc/hosts", 7, 17),
+            ]
+        );
+        assert_eq!(url_each("http://a.com:80/").len(), 4);
+        assert!(conn
+            .query_row("SELECT * FROM url_each('invalid')", [], |_| Ok(()))
+            .is_err());
+        // not split the way the parser reads them
+        for url in ["https:example.com/p", "https:/a.com/", "http://a.com/\tx"] {
+            assert!(
+                conn.query_row("SELECT * FROM url_each(?)", [url], |_| Ok(()))
+                    .is_err(),
+                "{}",
+                url
+            );
+        }
+        // only the special schemes read `\` as `/`
+        assert_eq!(
+            url_each("foo://a/b\\c"),
+            vec![
+                row("scheme", "foo", 0, 3),
+                row("host", "a", 6, 7),
+                row("path", "/b\\c", 7, 11),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_path_segments() -> Result<()> {
+        let conn = connect()?;
+
+        let segments = |url: &str| -> Vec<(i64, String, String)> {
+            conn.prepare("SELECT idx, segment, prefix FROM url_path_segments(?)")
+                .unwrap()
+                .query_map([url], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect()
+        };
+
+        assert_eq!(
+            segments("https://a.com/docs/my%20file/./v2/"),
+            vec![
+                (0, "docs".to_string(), "/docs".to_string()),
+                (1, "my file".to_string(), "/docs/my%20file".to_string()),
+                (2, "v2".to_string(), "/docs/my%20file/v2".to_string()),
+            ]
+        );
+        assert!(segments("https://a.com/").is_empty());
+        assert!(segments("mailto:someone@example.com").is_empty());
+
+        let top: (String, i64) = conn.query_row(
+            "SELECT prefix, count(*) FROM (SELECT 'https://a.com/docs/a' AS u UNION ALL SELECT 'https://a.com/docs/b' UNION ALL SELECT 'https://a.com/blog/c'), url_path_segments(u) WHERE idx = 0 GROUP BY prefix ORDER BY count(*) DESC LIMIT 1",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        assert_eq!(top, ("/docs".to_string(), 2));
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_extract_each() -> Result<()> {
+        let conn = connect()?;
+
+        let extract = |sql: &str, text: &str| -> Vec<(String, String, i64, i64)> {
+            conn.prepare(sql)
+                .unwrap()
+                .query_map([text], |row| {
+                    Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+                })
+                .unwrap()
+                .filter_map(Result::ok)
+                .collect()
+        };
+
+        let text = "Café menu at https://example.com/menu. Or example.org";
+        assert_eq!(
+            extract(
+                "SELECT raw, url, start, \"end\" FROM url_extract_each(?)",
+                text
+            ),
+            vec![(
+                "https://example.com/menu".to_string(),
+                "https://example.com/menu".to_string(),
+                14,
+                38
+            )]
+        );
+        let bare = extract(
+            "SELECT raw, url, start, \"end\" FROM url_extract_each(?, 1)",
+            text,
+        );
+        assert_eq!(bare.len(), 2);
+        assert_eq!(bare[1].1, "http://example.org/");
         Ok(())
     }
 }
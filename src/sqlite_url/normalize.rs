@@ -0,0 +1,294 @@
+use rusqlite::{functions::FunctionFlags, Connection, Error, Result};
+use serde_json::Value;
+use url::Url;
+
+use super::query_params::{name_matches, raw_pairs, set_raw_pairs};
+use super::UserError;
+use crate::function_stats::create_scalar_function;
+
+/// Query parameters dropped by `strip_tracking` unless `tracking_params` says otherwise.
+const TRACKING_PARAMS: &[&str] = &[
+    "utm_*", "fbclid", "gclid", "dclid", "gbraid", "wbraid", "msclkid", "mc_cid", "mc_eid",
+    "yclid", "igshid", "_ga", "_gl",
+];
+
+/// Rules applied by `url_normalize`, each one toggled by the key of the same name in its
+/// `options_json` argument.
+#[derive(Clone, Debug, PartialEq)]
+struct NormalizeOptions {
+    /// Hosts of the special schemes (`http`, `https`, `ws`, `wss`, `ftp`, `file`) are lowercased
+    /// when parsed, so this only affects other schemes.
+    lowercase_host: bool,
+    sort_query: bool,
+    strip_tracking: bool,
+    /// Names or `*`/`?` patterns removed by `strip_tracking`.
+    tracking_params: Vec<String>,
+    remove_empty_query: bool,
+    remove_empty_fragment: bool,
+    remove_trailing_slash: bool,
+}
+
+impl Default for NormalizeOptions {
+    fn default() -> Self {
+        NormalizeOptions {
+            lowercase_host: true,
+            sort_query: true,
+            strip_tracking: true,
+            tracking_params: TRACKING_PARAMS
+                .iter()
+                .map(|param| param.to_string())
+                .collect(),
+            remove_empty_query: true,
+            remove_empty_fragment: true,
+            remove_trailing_slash: false,
+        }
+    }
+}
+
+impl NormalizeOptions {
+    /// Starts from the defaults and applies the keys of the JSON object `json`.
+    fn from_json(json: &str) -> std::result::Result<NormalizeOptions, String> {
+        let value: Value = serde_json::from_str(json).map_err(|err| err.to_string())?;
+        let Value::Object(object) = value else {
+            return Err("options must be a JSON object".to_string());
+        };
+
+        let mut options = NormalizeOptions::default();
+        for (key, value) in object {
+            let flag = match key.as_str() {
+                "lowercase_host" => &mut options.lowercase_host,
+                "sort_query" => &mut options.sort_query,
+                "strip_tracking" => &mut options.strip_tracking,
+                "remove_empty_query" => &mut options.remove_empty_query,
+                "remove_empty_fragment" => &mut options.remove_empty_fragment,
+                "remove_trailing_slash" => &mut options.remove_trailing_slash,
+                "tracking_params" => {
+                    options.tracking_params = value
+                        .as_array()
+                        .and_then(|params| {
+                            params
+                                .iter()
+                                .map(|param| param.as_str().map(str::to_string))
+                                .collect()
+                        })
+                        .ok_or("tracking_params must be an array of strings")?;
+                    continue;
+                }
+                // the parser lowercases schemes, drops the default port of the special schemes
+                // and resolves dot segments, there is no way to keep them
+                "lowercase_scheme" | "remove_default_port" | "remove_dot_segments" => match value {
+                    Value::Bool(true) => continue,
+                    Value::Bool(false) => {
+                        return Err(format!(
+                            "{} is always applied by the URL parser and can't be turned off",
+                            key
+                        ))
+                    }
+                    _ => return Err(format!("{} must be a boolean", key)),
+                },
+                _ => return Err(format!("unknown option: {}", key)),
+            };
+            *flag = value
+                .as_bool()
+                .ok_or_else(|| format!("{} must be a boolean", key))?;
+        }
+        Ok(options)
+    }
+}
+
+fn normalize(mut url: Url, options: &NormalizeOptions) -> String {
+    if options.lowercase_host {
+        if let Some(host) = url.host_str().map(str::to_lowercase) {
+            // only fails for hosts that were not valid to begin with
+            let _ = url.set_host(Some(&host));
+        }
+    }
+
+    if options.remove_trailing_slash && !url.cannot_be_a_base() {
+        let path = url.path().trim_end_matches('/');
+        let path = if path.is_empty() { "/" } else { path }.to_string();
+        url.set_path(&path);
+    }
+
+    // parameters are matched and sorted by their decoded names but kept as written
+    if options.strip_tracking || options.sort_query {
+        let mut pairs = raw_pairs(&url);
+        if options.strip_tracking {
+            pairs.retain(|(key, _)| {
+                !options
+                    .tracking_params
+                    .iter()
+                    .any(|pattern| name_matches(pattern, key))
+            });
+        }
+        if options.sort_query {
+            // stable, so repeated parameters keep their relative order
+            pairs.sort_by(|(a, _), (b, _)| a.cmp(b));
+        }
+        set_raw_pairs(&mut url, pairs.into_iter().map(|(_, pair)| pair).collect());
+    }
+    if options.remove_empty_query && url.query() == Some("") {
+        url.set_query(None);
+    }
+    if options.remove_empty_fragment && url.fragment() == Some("") {
+        url.set_fragment(None);
+    }
+
+    url.to_string()
+}
+
+pub fn register_normalize_functions(conn: &Connection) -> Result<()> {
+    for n_arg in [1, 2] {
+        create_scalar_function(
+            conn,
+            "url_normalize",
+            n_arg,
+            FunctionFlags::SQLITE_DETERMINISTIC,
+            |ctx| {
+                let Some(url_text) = ctx.get::<Option<String>>(0)? else {
+                    return Ok(None);
+                };
+                let options = match ctx.len() {
+                    2 => match ctx.get::<Option<String>>(1)? {
+                        Some(json) => NormalizeOptions::from_json(&json).map_err(|err| {
+                            Error::UserFunctionError(Box::new(UserError(format!(
+                                "url_normalize(): {}",
+                                err
+                            ))))
+                        })?,
+                        None => NormalizeOptions::default(),
+                    },
+                    _ => NormalizeOptions::default(),
+                };
+                let url = Url::parse(&url_text)
+                    .map_err(|err| rusqlite::Error::UserFunctionError(err.into()))?;
+                Ok(Some(normalize(url, &options)))
+            },
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_normalize_functions(&conn).unwrap();
+        conn
+    }
+
+    fn normalized(conn: &Connection, url: &str, options: Option<&str>) -> String {
+        conn.query_row(
+            "SELECT url_normalize(?, ?)",
+            rusqlite::params![url, options],
+            |row| row.get(0),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_url_normalize_defaults() {
+        let conn = setup_connection();
+        let expected = "https://example.com/a/c?a=1&b=2";
+        for url in [
+            "HTTPS://Example.COM:443/a/b/../c?b=2&a=1&utm_source=news#",
+            "https://example.com/a/./c?fbclid=x&a=1&b=2",
+            "https://example.com/a/c?a=1&b=2&",
+        ] {
+            assert_eq!(normalized(&conn, url, None), expected, "{}", url);
+        }
+
+        // parameters are reordered and dropped but otherwise kept as written
+        assert_eq!(
+            normalized(
+                &conn,
+                "https://example.com/?q=a%20b&flag&utm_source=x&path=%2Fx~&a=c+d",
+                None
+            ),
+            "https://example.com/?a=c+d&flag&path=%2Fx~&q=a%20b"
+        );
+
+        let result: Option<String> = conn
+            .query_row("SELECT url_normalize(NULL)", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(result, None);
+    }
+
+    #[test]
+    fn test_url_normalize_non_special_scheme() {
+        let conn = setup_connection();
+        assert_eq!(
+            normalized(&conn, "myapp://Host.Example/x/../y/./z", None),
+            "myapp://host.example/y/z"
+        );
+        assert_eq!(
+            normalized(
+                &conn,
+                "myapp://Host.Example/x/../y",
+                Some(r#"{"lowercase_host": false}"#)
+            ),
+            "myapp://Host.Example/y"
+        );
+    }
+
+    #[test]
+    fn test_url_normalize_options() {
+        let conn = setup_connection();
+        let url = "https://example.com/path/?z=1&a=2&ref=x#";
+        assert_eq!(
+            normalized(
+                &conn,
+                url,
+                Some(
+                    r#"{"sort_query": false, "remove_trailing_slash": true, "tracking_params": ["ref"]}"#
+                )
+            ),
+            "https://example.com/path?z=1&a=2"
+        );
+        assert_eq!(
+            normalized(
+                &conn,
+                url,
+                Some(
+                    r#"{"strip_tracking": false, "sort_query": false, "remove_empty_fragment": false}"#
+                )
+            ),
+            "https://example.com/path/?z=1&a=2&ref=x#"
+        );
+        assert_eq!(
+            normalized(
+                &conn,
+                "HTTPS://example.com:443/a/../b?utm_source=x",
+                Some(
+                    r#"{"lowercase_scheme": true, "remove_default_port": true, "remove_dot_segments": true}"#
+                )
+            ),
+            "https://example.com/b"
+        );
+
+        for options in [
+            r#"{"sort": true}"#,
+            r#"{"sort_query": "yes"}"#,
+            r#"{"remove_dot_segments": false}"#,
+            r#"{"remove_default_port": 1}"#,
+            r#"{"tracking_params": "utm_*"}"#,
+            "[]",
+            "{",
+        ] {
+            assert!(
+                conn.query_row(
+                    "SELECT url_normalize('https://a.com', ?)",
+                    [options],
+                    |_| Ok(())
+                )
+                .is_err(),
+                "{}",
+                options
+            );
+        }
+    }
+}
@@ -0,0 +1,50 @@
+use percent_encoding::percent_decode_str;
+use rusqlite::{types::Value, Connection, Error, Result};
+use url::Url;
+
+use crate::{register_table_function, Rows, TableFunction};
+
+/// `url_path_segments(url)`: one `(idx, segment, prefix)` row per segment of the path of `url`,
+/// with `segment` percent-decoded and `prefix` the path up to and including it, as serialized.
+/// A trailing slash does not add an empty segment and URLs without a hierarchical path (e.g.
+/// `mailto:`) have no rows.
+struct PathSegments;
+
+impl TableFunction for PathSegments {
+    const COLUMNS: &'static [&'static str] = &["idx integer", "segment text", "prefix text"];
+    const ARGUMENTS: &'static [&'static str] = &["url"];
+    const FIRST_ROWID: i64 = 0;
+
+    fn rows(&self, args: &[Value]) -> Result<Rows> {
+        let Value::Text(url_text) = &args[0] else {
+            return Err(Error::ModuleError("url must be text.".to_string()));
+        };
+        let url = Url::parse(url_text)
+            .map_err(|err| Error::ModuleError(format!("invalid url: {}", err)))?;
+
+        let mut segments: Vec<&str> = url.path_segments().into_iter().flatten().collect();
+        if segments.last() == Some(&"") {
+            segments.pop();
+        }
+
+        let mut prefix = String::new();
+        let rows: Vec<Result<Vec<Value>>> = segments
+            .into_iter()
+            .enumerate()
+            .map(|(idx, segment)| {
+                prefix.push('/');
+                prefix.push_str(segment);
+                Ok(vec![
+                    Value::Integer(idx as i64),
+                    Value::Text(percent_decode_str(segment).decode_utf8_lossy().into_owned()),
+                    Value::Text(prefix.clone()),
+                ])
+            })
+            .collect();
+        Ok(Box::new(rows.into_iter()))
+    }
+}
+
+pub fn register_path_segments_virtual_table(conn: &Connection) -> Result<()> {
+    register_table_function(conn, "url_path_segments", PathSegments)
+}
@@ -0,0 +1,303 @@
+use rusqlite::{functions::Context, functions::FunctionFlags, Connection, Error, Result};
+use serde_json::{Map, Value};
+use std::sync::Arc;
+use url::Url;
+use urlpattern::{regexp::RegExp, UrlPattern, UrlPatternInit, UrlPatternMatchInput};
+
+use super::user_error;
+use crate::function_stats::create_scalar_function;
+
+/// Compiles a WHATWG URLPattern constructor string such as `https://*.example.com/users/:id`.
+/// Patterns are absolute: there is no base URL to resolve them against.
+fn compile<R: RegExp>(pattern: &str) -> std::result::Result<UrlPattern<R>, String> {
+    UrlPatternInit::parse_constructor_string::<R>(pattern, None)
+        .and_then(|init| UrlPattern::parse(init, Default::default()))
+        .map_err(|err| format!("invalid URL pattern {:?}: {}", pattern, err))
+}
+
+/// Groups captured by matching `pattern` against `url` as a JSON object, `None` when it doesn't
+/// match. Anonymous groups, from `*` and unnamed regexps, are keyed by component and index
+/// (`hostname.0`), and components left as a bare `*` are not reported at all.
+fn match_groups(pattern: &UrlPattern, url: &Url) -> Result<Option<Value>> {
+    let Some(result) = pattern
+        .exec(UrlPatternMatchInput::Url(url.clone()))
+        .map_err(|err| user_error(err.to_string()))?
+    else {
+        return Ok(None);
+    };
+
+    let components = [
+        ("protocol", pattern.protocol(), result.protocol),
+        ("username", pattern.username(), result.username),
+        ("password", pattern.password(), result.password),
+        ("hostname", pattern.hostname(), result.hostname),
+        ("port", pattern.port(), result.port),
+        ("pathname", pattern.pathname(), result.pathname),
+        ("search", pattern.search(), result.search),
+        ("hash", pattern.hash(), result.hash),
+    ];
+    let mut groups = Map::new();
+    for (component, component_pattern, component_result) in components {
+        if component_pattern == "*" {
+            continue;
+        }
+        // named groups first, then anonymous ones by index
+        let mut captured: Vec<_> = component_result.groups.into_iter().collect();
+        captured.sort_by_key(|(name, _)| (name.parse::<usize>().ok(), name.clone()));
+        for (name, value) in captured {
+            let key = match name.parse::<usize>() {
+                Ok(_) => format!("{}.{}", component, name),
+                Err(_) => name,
+            };
+            groups.insert(key, value.map_or(Value::Null, Value::String));
+        }
+    }
+    Ok(Some(Value::Object(groups)))
+}
+
+/// A rule of `url_match_first()`, `key` being its index or name.
+struct Rule {
+    key: (&'static str, Value),
+    pattern_text: String,
+    pattern: UrlPattern,
+}
+
+/// Parses the rules of `url_match_first()`: an array of patterns or an object of named ones, as
+/// built by `json_group_array()` and `json_group_object()`.
+fn parse_rules(json: &str) -> std::result::Result<Vec<Rule>, String> {
+    let rule = |key: (&'static str, Value), value: &Value| match value {
+        Value::String(pattern_text) => Ok(Rule {
+            key,
+            pattern_text: pattern_text.clone(),
+            pattern: compile(pattern_text)?,
+        }),
+        _ => Err(format!("pattern must be a string: {}", value)),
+    };
+    match serde_json::from_str(json) {
+        Ok(Value::Array(patterns)) => patterns
+            .iter()
+            .enumerate()
+            .map(|(i, pattern)| rule(("index", Value::from(i)), pattern))
+            .collect(),
+        Ok(Value::Object(patterns)) => patterns
+            .iter()
+            .map(|(name, pattern)| rule(("name", Value::from(name.as_str())), pattern))
+            .collect(),
+        Ok(_) => Err("patterns must be a JSON array or object".to_string()),
+        Err(err) => Err(err.to_string()),
+    }
+}
+
+/// Parses the absolute URL in argument `idx`, `None` for NULL.
+fn url_arg(ctx: &Context<'_>, idx: usize) -> Result<Option<Url>> {
+    let Some(url_text) = ctx.get::<Option<String>>(idx)? else {
+        return Ok(None);
+    };
+    Url::parse(&url_text)
+        .map(Some)
+        .map_err(|err| Error::UserFunctionError(err.into()))
+}
+
+pub fn register_pattern_functions(conn: &Connection) -> Result<()> {
+    // compiled patterns are cached for the statement when the argument is constant
+    create_scalar_function(
+        conn,
+        "url_match",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            if ctx.get::<Option<String>>(0)?.is_none() {
+                return Ok(None);
+            }
+            let pattern: Arc<UrlPattern> = ctx.get_or_create_aux(0, |value| {
+                compile(value.as_str().map_err(|err| err.to_string())?)
+                    .map_err(|err| format!("url_match(): {}", err))
+            })?;
+            let Some(url) = url_arg(ctx, 1)? else {
+                return Ok(None);
+            };
+            Ok(match_groups(&pattern, &url)?.map(|groups| groups.to_string()))
+        },
+    )?;
+
+    // {"index" or "name": ..., "pattern": ..., "groups": {...}} for the first matching rule
+    create_scalar_function(
+        conn,
+        "url_match_first",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            if ctx.get::<Option<String>>(0)?.is_none() {
+                return Ok(None);
+            }
+            let rules = ctx.get_or_create_aux(0, |value| {
+                parse_rules(value.as_str().map_err(|err| err.to_string())?)
+                    .map_err(|err| format!("url_match_first(): {}", err))
+            })?;
+            let Some(url) = url_arg(ctx, 1)? else {
+                return Ok(None);
+            };
+            for rule in rules.iter() {
+                if let Some(groups) = match_groups(&rule.pattern, &url)? {
+                    let mut matched = Map::new();
+                    matched.insert(rule.key.0.to_string(), rule.key.1.clone());
+                    matched.insert("pattern".to_string(), rule.pattern_text.clone().into());
+                    matched.insert("groups".to_string(), groups);
+                    return Ok(Some(Value::Object(matched).to_string()));
+                }
+            }
+            Ok(None)
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_pattern_functions(&conn).unwrap();
+        conn
+    }
+
+    fn call(conn: &Connection, sql: &str, pattern: &str, url: &str) -> Result<Option<String>> {
+        conn.query_row(sql, [pattern, url], |row| row.get(0))
+    }
+
+    #[test]
+    fn test_url_match() -> Result<()> {
+        let conn = setup_connection();
+        let url_match =
+            |pattern: &str, url: &str| call(&conn, "SELECT url_match(?, ?)", pattern, url);
+
+        assert_eq!(
+            url_match(
+                "https://*.example.com/users/:id",
+                "https://www.example.com/users/42?tab=posts"
+            )?
+            .as_deref(),
+            Some(r#"{"hostname.0":"www","id":"42"}"#)
+        );
+        assert_eq!(
+            url_match(
+                "http{s}?://example.com/:section/:slug(\\d+)?",
+                "http://example.com/blog"
+            )?
+            .as_deref(),
+            Some(r#"{"section":"blog","slug":null}"#)
+        );
+        assert_eq!(
+            url_match(
+                "https://example.com/files/*.pdf",
+                "https://example.com/files/a/b.pdf"
+            )?
+            .as_deref(),
+            Some(r#"{"pathname.0":"a/b"}"#)
+        );
+        assert_eq!(
+            url_match("https://example.com/", "https://example.com/")?.as_deref(),
+            Some("{}")
+        );
+        assert_eq!(
+            url_match(
+                "https://*.example.com/users/:id",
+                "https://example.org/users/42"
+            )?,
+            None
+        );
+        assert_eq!(
+            url_match(
+                "https://example.com/users/:id",
+                "https://example.com/users/42/posts"
+            )?,
+            None
+        );
+
+        assert!(url_match("/users/:id", "https://example.com/users/42").is_err());
+        assert!(url_match("https://example.com/:id(", "https://example.com/").is_err());
+        assert!(url_match("https://example.com/*", "not a url").is_err());
+        let null: Option<String> = conn.query_row(
+            "SELECT url_match(NULL, 'https://example.com/')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(null, None);
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_match_first() -> Result<()> {
+        let conn = setup_connection();
+        let match_first =
+            |rules: &str, url: &str| call(&conn, "SELECT url_match_first(?, ?)", rules, url);
+        let rules = r#"["https://example.com/users/:id", "https://example.com/*"]"#;
+        assert_eq!(
+            match_first(rules, "https://example.com/users/7")?.as_deref(),
+            Some(r#"{"index":0,"pattern":"https://example.com/users/:id","groups":{"id":"7"}}"#)
+        );
+        assert_eq!(
+            match_first(rules, "https://example.com/about")?.as_deref(),
+            Some(
+                r#"{"index":1,"pattern":"https://example.com/*","groups":{"pathname.0":"about"}}"#
+            )
+        );
+        assert_eq!(match_first(rules, "https://example.org/")?, None);
+
+        let named = r#"{"profile": "https://*.example.com/@:user", "other": "https://*"}"#;
+        assert_eq!(
+            match_first(named, "https://social.example.com/@ada")?.as_deref(),
+            Some(
+                r#"{"name":"profile","pattern":"https://*.example.com/@:user","groups":{"hostname.0":"social","user":"ada"}}"#
+            )
+        );
+
+        assert!(match_first("[1]", "https://example.com/").is_err());
+        assert!(match_first("\"https://*\"", "https://example.com/").is_err());
+        assert!(match_first("[", "https://example.com/").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_url_match_first_from_table() -> Result<()> {
+        let conn = setup_connection();
+        conn.execute_batch(
+            "CREATE TABLE rules (name TEXT, pattern TEXT);
+             INSERT INTO rules VALUES
+                ('article', 'https://news.example.com/:year(\\d+)/:slug'),
+                ('home', 'https://news.example.com/');
+             CREATE TABLE links (url TEXT);
+             INSERT INTO links VALUES
+                ('https://news.example.com/2024/elections'),
+                ('https://news.example.com/'),
+                ('https://example.org/');",
+        )?;
+        let mut stmt = conn.prepare(
+            "SELECT url, url_match_first(
+                (SELECT json_group_object(name, pattern) FROM rules), url
+             ) ->> '$.name'
+             FROM links ORDER BY rowid",
+        )?;
+        let classified: Vec<(String, Option<String>)> = stmt
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<Result<_>>()?;
+        assert_eq!(
+            classified,
+            vec![
+                (
+                    "https://news.example.com/2024/elections".to_string(),
+                    Some("article".to_string())
+                ),
+                (
+                    "https://news.example.com/".to_string(),
+                    Some("home".to_string())
+                ),
+                ("https://example.org/".to_string(), None),
+            ]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,169 @@
+use publicsuffix::{List, Psl};
+use rusqlite::{functions::FunctionFlags, Connection, Result};
+use std::sync::OnceLock;
+use url::Url;
+
+use super::extraction::register_extractor;
+use crate::function_stats::create_scalar_function;
+
+/// Snapshot of https://publicsuffix.org/list/public_suffix_list.dat, ICANN and private sections.
+const PUBLIC_SUFFIX_LIST: &str = include_str!("public_suffix_list.dat");
+
+/// Date of the snapshot above, reported by `url_psl_version()`; update both together.
+const PUBLIC_SUFFIX_LIST_VERSION: &str = "2023-02-09";
+
+fn public_suffix_list() -> &'static List {
+    static LIST: OnceLock<List> = OnceLock::new();
+    LIST.get_or_init(|| {
+        PUBLIC_SUFFIX_LIST
+            .parse()
+            .expect("embedded public suffix list is valid")
+    })
+}
+
+/// The lowercased domain of `url` without a trailing dot, `None` for IP addresses and URLs
+/// without a host.
+fn domain_name(url: &Url) -> Option<String> {
+    let domain = url.domain()?.trim_end_matches('.');
+    (!domain.is_empty()).then(|| domain.to_ascii_lowercase())
+}
+
+/// Public suffix of `domain`. Like browsers, a TLD missing from the list is a public suffix of
+/// its own (the implicit `*` rule).
+fn public_suffix(domain: &str) -> Option<String> {
+    let suffix = public_suffix_list().suffix(domain.as_bytes())?;
+    String::from_utf8(suffix.as_bytes().to_vec()).ok()
+}
+
+/// Registrable domain of `domain`: its public suffix and one more label, `None` when `domain`
+/// is a public suffix itself.
+fn registrable_domain(domain: &str) -> Option<String> {
+    let registrable = public_suffix_list().domain(domain.as_bytes())?;
+    String::from_utf8(registrable.as_bytes().to_vec()).ok()
+}
+
+/// Whether `domain` has a registrable domain under a suffix that is actually on the list, as
+/// opposed to one only matched by the implicit `*` rule.
+pub(super) fn has_listed_suffix(domain: &str) -> bool {
+    let domain = domain.to_ascii_lowercase();
+    public_suffix_list()
+        .domain(domain.as_bytes())
+        .is_some_and(|registrable| registrable.suffix().is_known())
+}
+
+pub fn register_public_suffix_functions(conn: &Connection) -> Result<()> {
+    register_extractor(conn, "url_domain", |url| {
+        registrable_domain(&domain_name(url)?)
+    })?;
+    register_extractor(conn, "url_tld", |url| public_suffix(&domain_name(url)?))?;
+    // "" for the registrable domain itself, NULL when there is none
+    register_extractor(conn, "url_subdomain", |url| {
+        let domain = domain_name(url)?;
+        let registrable = registrable_domain(&domain)?;
+        let subdomain = domain.strip_suffix(registrable.as_str())?;
+        Some(subdomain.trim_end_matches('.').to_string())
+    })?;
+
+    create_scalar_function(
+        conn,
+        "domain_is_public_suffix",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(domain) = ctx.get::<Option<String>>(0)? else {
+                return Ok(None);
+            };
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            Ok(Some(
+                !domain.is_empty() && public_suffix(&domain).as_deref() == Some(domain.as_str()),
+            ))
+        },
+    )?;
+
+    create_scalar_function(
+        conn,
+        "url_psl_version",
+        0,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |_ctx| Ok(PUBLIC_SUFFIX_LIST_VERSION),
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_public_suffix_functions(&conn).unwrap();
+        conn
+    }
+
+    fn query(conn: &Connection, sql: &str, url: &str) -> Option<String> {
+        conn.query_row(sql, [url], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_url_domain_tld_subdomain() {
+        let conn = setup_connection();
+        let parts = |url: &str| {
+            (
+                query(&conn, "SELECT url_domain(?)", url),
+                query(&conn, "SELECT url_tld(?)", url),
+                query(&conn, "SELECT url_subdomain(?)", url),
+            )
+        };
+        let some = |value: &str| Some(value.to_string());
+
+        assert_eq!(
+            parts("https://a.b.Example.co.uk/path"),
+            (some("example.co.uk"), some("co.uk"), some("a.b"))
+        );
+        assert_eq!(
+            parts("https://example.co.uk./"),
+            (some("example.co.uk"), some("co.uk"), some(""))
+        );
+        // private section entries count as public suffixes
+        assert_eq!(
+            parts("https://user.github.io/"),
+            (some("user.github.io"), some("github.io"), some(""))
+        );
+        // unlisted TLDs fall back to the implicit `*` rule
+        assert_eq!(
+            parts("http://www.example.internal/"),
+            (some("example.internal"), some("internal"), some("www"))
+        );
+        assert_eq!(parts("https://co.uk/"), (None, some("co.uk"), None));
+        assert_eq!(parts("http://127.0.0.1/"), (None, None, None));
+        assert_eq!(parts("mailto:someone@example.com"), (None, None, None));
+
+        assert_eq!(query(&conn, "SELECT url_try_domain(?)", "invalid"), None);
+        assert!(conn
+            .query_row("SELECT url_domain('invalid')", [], |_| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_domain_is_public_suffix() {
+        let conn = setup_connection();
+        let is_public_suffix = |domain: &str| -> bool {
+            conn.query_row("SELECT domain_is_public_suffix(?)", [domain], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+        assert!(is_public_suffix("com"));
+        assert!(is_public_suffix("CO.UK"));
+        assert!(is_public_suffix("github.io"));
+        assert!(!is_public_suffix("example.co.uk"));
+        assert!(!is_public_suffix(""));
+
+        let version: String = conn
+            .query_row("SELECT url_psl_version()", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, PUBLIC_SUFFIX_LIST_VERSION);
+    }
+}
@@ -1,131 +1,150 @@
 use rusqlite::{
-    ffi,
-    vtab::{
-        self, eponymous_only_module, CreateVTab, IndexConstraintOp, IndexInfo, VTab, VTabCursor,
-        VTabKind,
-    },
+    functions::{Aggregate, Context, FunctionFlags},
+    types::Value,
     Connection, Error, Result,
 };
-use std::os::raw::c_int;
-
-#[repr(C)]
-struct QueryEachTable {
-    base: ffi::sqlite3_vtab,
+use std::collections::HashMap;
+use url::form_urlencoded;
+
+use crate::function_stats::create_aggregate_function;
+use crate::{register_table_function, Rows, TableFunction};
+
+/// Whether `input` starts with `scheme://`. Names such as `ns:key` are valid in a query string,
+/// so a colon alone doesn't make a URL.
+fn is_url(input: &str) -> bool {
+    input.split_once("://").is_some_and(|(scheme, _)| {
+        scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+    })
 }
 
-unsafe impl<'vtab> VTab<'vtab> for QueryEachTable {
-    type Aux = ();
-    type Cursor = QueryEachCursor;
-
-    fn connect(
-        _db: &mut vtab::VTabConnection,
-        _aux: Option<&Self::Aux>,
-        _args: &[&[u8]],
-    ) -> Result<(String, Self)> {
-        let schema = "CREATE TABLE x(query hidden, name text, value text)";
-        Ok((
-            schema.to_string(),
-            QueryEachTable {
-                base: ffi::sqlite3_vtab::default(),
-            },
-        ))
+/// Byte ranges of the query and fragment of `input`. A URL, starting with `scheme://`, is split
+/// as in RFC 3986 appendix B, anything else is a query string on its own, with an optional
+/// leading `?`.
+fn parameter_ranges(input: &str, fragment: bool) -> Vec<(&'static str, usize, usize)> {
+    if let Some(query) = input.strip_prefix('?') {
+        return vec![("query", 1, 1 + query.len())];
     }
-
-    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
-        let relevant_constraints: Vec<(usize, i32)> = info
-            .constraints()
-            .enumerate()
-            .filter_map(|(i, constraint)| {
-                if constraint.column() == 0
-                    && constraint.is_usable()
-                    && constraint.operator() == IndexConstraintOp::SQLITE_INDEX_CONSTRAINT_EQ
-                {
-                    Some((i, 1))
-                } else {
-                    None
-                }
-            })
-            .collect();
-
-        for (index, argv_index) in relevant_constraints {
-            let mut usage = info.constraint_usage(index);
-            usage.set_argv_index(argv_index);
-            usage.set_omit(true);
-        }
-
-        info.set_estimated_cost(1.0);
-
-        Ok(())
+    if !is_url(input) {
+        return vec![("query", 0, input.len())];
     }
 
-    fn open(&mut self) -> Result<Self::Cursor> {
-        Ok(QueryEachCursor {
-            rows: vec![],
-            index: 0,
-            base: ffi::sqlite3_vtab_cursor::default(),
-        })
+    let hash = input.find('#').unwrap_or(input.len());
+    let mut ranges = vec![];
+    if let Some(question) = input[..hash].find('?') {
+        ranges.push(("query", question + 1, hash));
     }
+    if fragment && hash < input.len() {
+        ranges.push(("fragment", hash + 1, input.len()));
+    }
+    ranges
 }
 
-impl CreateVTab<'_> for QueryEachTable {
-    const KIND: VTabKind = VTabKind::EponymousOnly;
-}
-
-#[repr(C)]
-struct QueryEachCursor {
-    base: ffi::sqlite3_vtab_cursor,
-    rows: Vec<(String, String)>,
-    index: usize,
-}
-
-unsafe impl VTabCursor for QueryEachCursor {
-    fn filter(
-        &mut self,
-        _idx_num: c_int,
-        _idx_str: Option<&str>,
-        args: &vtab::Values<'_>,
-    ) -> Result<()> {
-        if args.is_empty() {
-            return Err(Error::ModuleError(
-                "Missing required query argument.".to_string(),
-            ));
+/// `url_query_each(query, fragment)`: one row per pair of the urlencoded query string `query`,
+/// or of the query of `query` when it is a URL, along with the pairs of its fragment when
+/// `fragment` is true (e.g. `#access_token=...`). Rowids count the pairs from 1.
+///
+/// `name`/`value` are decoded, `raw_name`/`raw_value` as written, `start`/`end` the byte offsets
+/// of the pair in `query`, `end` exclusive, and `occurrence` counts the earlier pairs with the
+/// same name, 0 for the first one. `component` is `query` or `fragment`. A pair without `=` has
+/// an empty value and a NULL `raw_value`.
+struct QueryEach;
+
+impl TableFunction for QueryEach {
+    const COLUMNS: &'static [&'static str] = &[
+        "name text",
+        "value text",
+        "raw_name text",
+        "raw_value text",
+        "start integer",
+        "end integer",
+        "occurrence integer",
+        "component text",
+    ];
+    const ARGUMENTS: &'static [&'static str] = &["query", "fragment"];
+
+    fn rows(&self, args: &[Value]) -> Result<Rows> {
+        let Value::Text(input) = &args[0] else {
+            return Err(Error::ModuleError("query must be text.".to_string()));
+        };
+        let fragment = match args[1] {
+            Value::Null => false,
+            Value::Integer(flag) => flag != 0,
+            _ => {
+                return Err(Error::ModuleError(
+                    "fragment must be a boolean.".to_string(),
+                ))
+            }
+        };
+
+        let mut occurrences: HashMap<String, usize> = HashMap::new();
+        let mut rows: Vec<Result<Vec<Value>>> = vec![];
+        for (component, start, end) in parameter_ranges(input, fragment) {
+            let mut pair_start = start;
+            for pair in input[start..end].split('&') {
+                let pair_end = pair_start + pair.len();
+                if let Some((name, value)) = form_urlencoded::parse(pair.as_bytes()).next() {
+                    let (raw_name, raw_value) = match pair.split_once('=') {
+                        Some((raw_name, raw_value)) => (raw_name, Value::Text(raw_value.into())),
+                        None => (pair, Value::Null),
+                    };
+                    let name = name.into_owned();
+                    let occurrence = occurrences.entry(name.clone()).or_insert(0);
+                    rows.push(Ok(vec![
+                        Value::Text(name),
+                        Value::Text(value.into_owned()),
+                        Value::Text(raw_name.to_string()),
+                        raw_value,
+                        Value::Integer(pair_start as i64),
+                        Value::Integer(pair_end as i64),
+                        Value::Integer(*occurrence as i64),
+                        Value::Text(component.to_string()),
+                    ]));
+                    *occurrence += 1;
+                }
+                pair_start = pair_end + 1;
+            }
         }
-
-        let query = args.get::<String>(0)?.to_string();
-        self.rows = url::form_urlencoded::parse(query.as_bytes())
-            .into_owned()
-            .collect();
-        self.index = 0;
-        Ok(())
+        Ok(Box::new(rows.into_iter()))
     }
+}
 
-    fn next(&mut self) -> Result<()> {
-        self.index += 1;
-        Ok(())
-    }
+/// `url_query_build(name, value)`: the urlencoded query string of the pairs of a group, in
+/// the order they are aggregated. Rows with a NULL name are skipped and a NULL value gives a
+/// name without `=`.
+struct QueryBuild;
 
-    fn eof(&self) -> bool {
-        self.index >= self.rows.len()
+impl Aggregate<String, String> for QueryBuild {
+    fn init(&self, _ctx: &mut Context<'_>) -> Result<String> {
+        Ok(String::new())
     }
 
-    fn column(&self, ctx: &mut vtab::Context, col: i32) -> Result<()> {
-        match col {
-            1 => ctx.set_result(&self.rows[self.index].0),
-            2 => ctx.set_result(&self.rows[self.index].1),
-            _ => Ok(()),
-        }
+    fn step(&self, ctx: &mut Context<'_>, acc: &mut String) -> Result<()> {
+        let Some(name) = ctx.get::<Option<String>>(0)? else {
+            return Ok(());
+        };
+        let mut serializer = form_urlencoded::Serializer::for_suffix(acc, 0);
+        match ctx.get::<Option<String>>(1)? {
+            Some(value) => serializer.append_pair(&name, &value),
+            None => serializer.append_key_only(&name),
+        };
+        Ok(())
     }
 
-    fn rowid(&self) -> Result<i64> {
-        Ok(self.index as i64)
+    fn finalize(&self, _ctx: &mut Context<'_>, acc: Option<String>) -> Result<String> {
+        Ok(acc.unwrap_or_default())
     }
 }
 
-pub fn register_query_each_virtual_table(conn: &Connection) -> Result<()> {
-    conn.create_module(
-        "url_query_each",
-        eponymous_only_module::<QueryEachTable>(),
-        None,
+pub fn register_query_each_functions(conn: &Connection) -> Result<()> {
+    register_table_function(conn, "url_query_each", QueryEach)?;
+    create_aggregate_function(
+        conn,
+        "url_query_build",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        QueryBuild,
     )
 }
-
@@ -0,0 +1,347 @@
+use rusqlite::{functions::Context, functions::FunctionFlags, Connection, Error, Result};
+use url::{form_urlencoded, Url};
+
+use super::UserError;
+use crate::function_stats::create_scalar_function;
+
+/// Parses the URL in the first argument, `None` for NULL.
+fn url_arg(ctx: &Context<'_>) -> Result<Option<Url>> {
+    let Some(url_text) = ctx.get::<Option<String>>(0)? else {
+        return Ok(None);
+    };
+    Url::parse(&url_text)
+        .map(Some)
+        .map_err(|err| Error::UserFunctionError(err.into()))
+}
+
+/// The `&`-separated pairs of the query of `url` as written, each with its decoded name. Pairs
+/// are kept as they are so that editing one parameter leaves the encoding of the others alone;
+/// empty ones (`a=1&&b=2`) are dropped, as the form parser does.
+pub(super) fn raw_pairs(url: &Url) -> Vec<(String, String)> {
+    url.query()
+        .unwrap_or("")
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let name = form_urlencoded::parse(pair.as_bytes())
+                .next()
+                .map(|(name, _)| name.into_owned())
+                .unwrap_or_default();
+            (name, pair.to_string())
+        })
+        .collect()
+}
+
+/// Replaces the query of `url` with the raw `pairs`, dropping the `?` altogether when there are
+/// none.
+pub(super) fn set_raw_pairs(url: &mut Url, pairs: Vec<String>) {
+    if pairs.is_empty() {
+        url.set_query(None);
+    } else {
+        url.set_query(Some(&pairs.join("&")));
+    }
+}
+
+/// `name=value`, form-encoded.
+fn encode_pair(name: &str, value: &str) -> String {
+    form_urlencoded::Serializer::new(String::new())
+        .append_pair(name, value)
+        .finish()
+}
+
+/// Matches `name` against a pattern where `*` stands for any run of characters and `?` for a
+/// single one; patterns without either must match exactly.
+pub(super) fn name_matches(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // two pointers, backtracking only to just after the last `*`, which is O(n·m)
+    let (mut p, mut n) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                last_star = Some((p, n));
+                p += 1;
+            }
+            Some(&c) if c == '?' || c == name[n] => {
+                p += 1;
+                n += 1;
+            }
+            _ => match last_star {
+                // let the last `*` swallow one more character
+                Some((star, matched)) => {
+                    last_star = Some((star, matched + 1));
+                    p = star + 1;
+                    n = matched + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+pub fn register_query_param_functions(conn: &Connection) -> Result<()> {
+    create_scalar_function(
+        conn,
+        "url_query_get",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(url) = url_arg(ctx)? else {
+                return Ok(None);
+            };
+            let name: String = ctx.get(1)?;
+            Ok(url
+                .query_pairs()
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.into_owned()))
+        },
+    )?;
+
+    create_scalar_function(
+        conn,
+        "url_query_set",
+        3,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(mut url) = url_arg(ctx)? else {
+                return Ok(None);
+            };
+            let name: String = ctx.get(1)?;
+            let value: String = ctx.get(2)?;
+
+            // the first occurrence keeps its place, later ones are dropped
+            let mut replaced = false;
+            let mut pairs: Vec<String> = raw_pairs(&url)
+                .into_iter()
+                .filter_map(|(key, pair)| match key == name {
+                    false => Some(pair),
+                    true if replaced => None,
+                    true => {
+                        replaced = true;
+                        Some(encode_pair(&name, &value))
+                    }
+                })
+                .collect();
+            if !replaced {
+                pairs.push(encode_pair(&name, &value));
+            }
+            set_raw_pairs(&mut url, pairs);
+            Ok(Some(url.to_string()))
+        },
+    )?;
+
+    create_scalar_function(
+        conn,
+        "url_query_append",
+        3,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(mut url) = url_arg(ctx)? else {
+                return Ok(None);
+            };
+            let name: String = ctx.get(1)?;
+            let value: String = ctx.get(2)?;
+            url.query_pairs_mut().append_pair(&name, &value);
+            Ok(Some(url.to_string()))
+        },
+    )?;
+
+    create_scalar_function(
+        conn,
+        "url_query_remove",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(mut url) = url_arg(ctx)? else {
+                return Ok(None);
+            };
+            let pattern: String = ctx.get(1)?;
+            if pattern.is_empty() {
+                return Err(Error::UserFunctionError(Box::new(UserError(
+                    "url_query_remove() requires a non-empty name or pattern".to_string(),
+                ))));
+            }
+            let pairs = raw_pairs(&url)
+                .into_iter()
+                .filter(|(key, _)| !name_matches(&pattern, key))
+                .map(|(_, pair)| pair)
+                .collect();
+            set_raw_pairs(&mut url, pairs);
+            Ok(Some(url.to_string()))
+        },
+    )?;
+
+    create_scalar_function(
+        conn,
+        "url_query_clear",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(mut url) = url_arg(ctx)? else {
+                return Ok(None);
+            };
+            url.set_query(None);
+            Ok(Some(url.to_string()))
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_query_param_functions(&conn).unwrap();
+        conn
+    }
+
+    fn query(conn: &Connection, sql: &str) -> Option<String> {
+        conn.query_row(sql, [], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_url_query_get() {
+        let conn = setup_connection();
+        assert_eq!(
+            query(
+                &conn,
+                "SELECT url_query_get('https://a.com/?x=1&y=a%20b&x=2', 'y')"
+            )
+            .as_deref(),
+            Some("a b")
+        );
+        assert_eq!(
+            query(&conn, "SELECT url_query_get('https://a.com/?x=1&x=2', 'x')").as_deref(),
+            Some("1")
+        );
+        assert_eq!(
+            query(&conn, "SELECT url_query_get('https://a.com/?x=1', 'z')"),
+            None
+        );
+        assert_eq!(query(&conn, "SELECT url_query_get(NULL, 'z')"), None);
+        assert!(conn
+            .query_row("SELECT url_query_get('invalid', 'x')", [], |_| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_url_query_set() {
+        let conn = setup_connection();
+        assert_eq!(
+            query(
+                &conn,
+                "SELECT url_query_set('https://a.com/p?x=1&y=2&x=3#f', 'x', 'new value')"
+            )
+            .as_deref(),
+            Some("https://a.com/p?x=new+value&y=2#f")
+        );
+        assert_eq!(
+            query(&conn, "SELECT url_query_set('https://a.com/', 'x', '1')").as_deref(),
+            Some("https://a.com/?x=1")
+        );
+    }
+
+    #[test]
+    fn test_untouched_parameters_keep_their_encoding() {
+        let conn = setup_connection();
+        let edit = |sql: &str| -> String {
+            conn.query_row(
+                sql,
+                ["https://a.com/?flag&q=a%20b&path=%2Fx~&q2=a+b"],
+                |row| row.get(0),
+            )
+            .unwrap()
+        };
+        assert_eq!(
+            edit("SELECT url_query_set(?, 'x', '1')"),
+            "https://a.com/?flag&q=a%20b&path=%2Fx~&q2=a+b&x=1"
+        );
+        assert_eq!(
+            edit("SELECT url_query_set(?, 'q', 'c d')"),
+            "https://a.com/?flag&q=c+d&path=%2Fx~&q2=a+b"
+        );
+        assert_eq!(
+            edit("SELECT url_query_remove(?, 'q2')"),
+            "https://a.com/?flag&q=a%20b&path=%2Fx~"
+        );
+        assert_eq!(
+            edit("SELECT url_query_remove(?, 'flag')"),
+            "https://a.com/?q=a%20b&path=%2Fx~&q2=a+b"
+        );
+    }
+
+    #[test]
+    fn test_url_query_append() {
+        let conn = setup_connection();
+        assert_eq!(
+            query(
+                &conn,
+                "SELECT url_query_append('https://a.com/?x=1', 'x', '2')"
+            )
+            .as_deref(),
+            Some("https://a.com/?x=1&x=2")
+        );
+    }
+
+    #[test]
+    fn test_url_query_remove() {
+        let conn = setup_connection();
+        assert_eq!(
+            query(
+                &conn,
+                "SELECT url_query_remove('https://a.com/?utm_source=x&id=1&utm_medium=y', 'utm_*')"
+            )
+            .as_deref(),
+            Some("https://a.com/?id=1")
+        );
+        assert_eq!(
+            query(
+                &conn,
+                "SELECT url_query_remove('https://a.com/?id=1&idx=2', 'id')"
+            )
+            .as_deref(),
+            Some("https://a.com/?idx=2")
+        );
+        assert_eq!(
+            query(
+                &conn,
+                "SELECT url_query_remove('https://a.com/?id=1#top', 'i?')"
+            )
+            .as_deref(),
+            Some("https://a.com/#top")
+        );
+    }
+
+    #[test]
+    fn test_name_matches() {
+        assert!(name_matches("utm_*", "utm_source"));
+        assert!(name_matches("*_id", "user_id"));
+        assert!(name_matches("a*b*c", "axxbyybc"));
+        assert!(name_matches("i?", "id"));
+        assert!(name_matches("*", ""));
+        assert!(name_matches("é?", "éa"));
+        assert!(!name_matches("i?", "i"));
+        assert!(!name_matches("id", "idx"));
+        assert!(!name_matches("a*b", "axxbc"));
+
+        // backtracking over every `*` would take forever here
+        let name = "a".repeat(100);
+        assert!(!name_matches(&format!("{}b", "a*".repeat(50)), &name));
+    }
+
+    #[test]
+    fn test_url_query_clear() {
+        let conn = setup_connection();
+        assert_eq!(
+            query(&conn, "SELECT url_query_clear('https://a.com/p?x=1&y=2#f')").as_deref(),
+            Some("https://a.com/p#f")
+        );
+    }
+}
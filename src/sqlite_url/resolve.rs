@@ -0,0 +1,282 @@
+use rusqlite::{functions::Context, functions::FunctionFlags, Connection, Error, Result};
+use url::{Position, Url};
+
+use crate::function_stats::create_scalar_function;
+
+/// Parses the absolute URL in argument `idx`, `None` for NULL.
+fn url_arg(ctx: &Context<'_>, idx: usize) -> Result<Option<Url>> {
+    let Some(url_text) = ctx.get::<Option<String>>(idx)? else {
+        return Ok(None);
+    };
+    Url::parse(&url_text)
+        .map(Some)
+        .map_err(|err| Error::UserFunctionError(err.into()))
+}
+
+/// Shortest reference that resolves to `to` against `from`, or `to` itself when the two don't
+/// share a scheme.
+fn relative_reference(from: &Url, to: &Url) -> String {
+    if from.scheme() != to.scheme() || from.cannot_be_a_base() || to.cannot_be_a_base() {
+        return to.to_string();
+    }
+
+    let same_authority = from.username() == to.username()
+        && from.password() == to.password()
+        && from.host() == to.host()
+        && from.port() == to.port();
+    let reference = if same_authority {
+        let mut reference = path_reference(from, to);
+        if let Some(fragment) = to.fragment() {
+            reference.push('#');
+            reference.push_str(fragment);
+        }
+        reference
+    } else {
+        format!("//{}", &to[Position::BeforeUsername..])
+    };
+
+    // quirks of the special schemes, such as Windows drive letters in `file:` paths, can still
+    // resolve elsewhere
+    match from.join(&reference) {
+        Ok(resolved) if resolved == *to => reference,
+        _ => to.to_string(),
+    }
+}
+
+/// The path and query part of a reference from `from` to `to` on the same authority.
+fn path_reference(from: &Url, to: &Url) -> String {
+    let query = to.query().map(|query| format!("?{}", query));
+    if from.path() == to.path() {
+        return match (query, from.query()) {
+            // an empty reference keeps the query of `from`
+            (None, None) => String::new(),
+            (Some(query), _) => query,
+            (None, Some(_)) => relative_path(last_segment(to.path()), ""),
+        };
+    }
+
+    // `from` without its last segment, against `to` up to its last segment, which is always
+    // written out
+    let from_dir: Vec<&str> = from
+        .path()
+        .rsplit_once('/')
+        .map_or(vec![], |(dir, _)| dir.split('/').collect());
+    let to_segments: Vec<&str> = to.path().split('/').collect();
+    let common = from_dir
+        .iter()
+        .zip(&to_segments[..to_segments.len() - 1])
+        .take_while(|(a, b)| a == b)
+        .count();
+    let up = "../".repeat(from_dir.len() - common);
+    let relative = relative_path(&to_segments[common..].join("/"), &up);
+
+    // `//host` would be read as an authority, `/.//host` keeps it a path
+    let absolute = if to.path().starts_with("//") {
+        format!("/.{}", to.path())
+    } else {
+        to.path().to_string()
+    };
+    let mut path = if relative.len() <= absolute.len() {
+        relative
+    } else {
+        absolute
+    };
+    path.push_str(query.as_deref().unwrap_or(""));
+    path
+}
+
+/// `up` followed by the relative path `path`, with a `./` prefix when it would otherwise be empty,
+/// start with `/` or read as a scheme (`a:b`).
+fn relative_path(path: &str, up: &str) -> String {
+    let first_segment = path.split('/').next().unwrap_or("");
+    if up.is_empty() && (path.is_empty() || path.starts_with('/') || first_segment.contains(':')) {
+        format!("./{}", path)
+    } else {
+        format!("{}{}", up, path)
+    }
+}
+
+fn last_segment(path: &str) -> &str {
+    path.rsplit('/').next().unwrap_or(path)
+}
+
+pub fn register_resolve_functions(conn: &Connection) -> Result<()> {
+    create_scalar_function(
+        conn,
+        "url_resolve",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(base) = url_arg(ctx, 0)? else {
+                return Ok(None);
+            };
+            let Some(reference) = ctx.get::<Option<String>>(1)? else {
+                return Ok(None);
+            };
+            base.join(&reference)
+                .map(|url| Some(url.to_string()))
+                .map_err(|err| Error::UserFunctionError(err.into()))
+        },
+    )?;
+
+    create_scalar_function(
+        conn,
+        "url_relative",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let (Some(from), Some(to)) = (url_arg(ctx, 0)?, url_arg(ctx, 1)?) else {
+                return Ok(None);
+            };
+            Ok(Some(relative_reference(&from, &to)))
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_resolve_functions(&conn).unwrap();
+        conn
+    }
+
+    fn call(conn: &Connection, function: &str, a: &str, b: &str) -> Option<String> {
+        conn.query_row(&format!("SELECT {}(?, ?)", function), [a, b], |row| {
+            row.get(0)
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_url_resolve() {
+        let conn = setup_connection();
+        let base = "http://a/b/c/d;p?q";
+        // RFC 3986, section 5.4
+        for (reference, expected) in [
+            ("g", "http://a/b/c/g"),
+            ("./g/", "http://a/b/c/g/"),
+            ("/g", "http://a/g"),
+            ("//g", "http://g/"),
+            ("?y", "http://a/b/c/d;p?y"),
+            ("#s", "http://a/b/c/d;p?q#s"),
+            ("../../g", "http://a/g"),
+            ("../../../../g", "http://a/g"),
+            ("", "http://a/b/c/d;p?q"),
+            ("https://other.org/x", "https://other.org/x"),
+        ] {
+            assert_eq!(
+                call(&conn, "url_resolve", base, reference).as_deref(),
+                Some(expected),
+                "{}",
+                reference
+            );
+        }
+
+        let null: Option<String> = conn
+            .query_row("SELECT url_resolve('http://a/', NULL)", [], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert_eq!(null, None);
+        assert!(conn
+            .query_row("SELECT url_resolve('not a url', 'g')", [], |_| Ok(()))
+            .is_err());
+        assert!(conn
+            .query_row("SELECT url_resolve('http://a/', '//[::1')", [], |_| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_url_relative() {
+        let conn = setup_connection();
+        for (from, to, expected) in [
+            ("https://a.com/x/y/z", "https://a.com/x/q", "../q"),
+            ("https://a.com/x/", "https://a.com/x/y", "y"),
+            ("https://a.com/a/b/c", "https://a.com/d", "/d"),
+            ("https://a.com/x/y?a=1", "https://a.com/x/y?b=2", "?b=2"),
+            ("https://a.com/x/y#f", "https://a.com/x/y#g", "#g"),
+            ("https://a.com/x/y", "https://a.com/x/a:b", "./a:b"),
+            ("https://a.com/x/y", "https://a.com/x/y", ""),
+            ("https://a.com/x?q", "https://a.com/x", "x"),
+            ("https://a.com/x/?q", "https://a.com/x/#f", "./#f"),
+            ("https://a.com/x/y/z", "https://a.com/x/y", "../y"),
+            (
+                "https://a.com/",
+                "https://a.com//evil.com/x",
+                ".//evil.com/x",
+            ),
+            (
+                "https://a.com/a/b/c",
+                "https://a.com//evil.com",
+                "/.//evil.com",
+            ),
+            (
+                "https://a.com/x/",
+                "https://b.com:8443/x/",
+                "//b.com:8443/x/",
+            ),
+            ("https://a.com/x/", "http://a.com/x/", "http://a.com/x/"),
+            ("mailto:a@b.com", "mailto:c@d.com", "mailto:c@d.com"),
+        ] {
+            let relative = call(&conn, "url_relative", from, to).unwrap();
+            assert_eq!(relative, expected, "{} -> {}", from, to);
+            assert_eq!(
+                call(&conn, "url_resolve", from, &relative),
+                Some(Url::parse(to).unwrap().to_string()),
+                "{} -> {}",
+                from,
+                to
+            );
+        }
+    }
+
+    #[test]
+    fn test_url_relative_resolves_back() -> Result<()> {
+        let conn = setup_connection();
+        let mut urls = vec![];
+        for host in ["a.com", "b.com"] {
+            for path in [
+                "/",
+                "/x",
+                "/x/",
+                "/x/y",
+                "/x/y/",
+                "/y",
+                "//evil.com/x",
+                "//",
+                "/a:b",
+                "/x//y",
+                "/%2E%2E/x",
+                "/x/%2F",
+            ] {
+                for query in ["", "?", "?q", "?b=2"] {
+                    for fragment in ["", "#", "#f"] {
+                        urls.push(format!("https://{}{}{}{}", host, path, query, fragment));
+                    }
+                }
+            }
+        }
+
+        // url_resolve(a, url_relative(a, b)) = b for every pair
+        let mut stmt = conn.prepare("SELECT url_resolve(?1, url_relative(?1, ?2))")?;
+        for from in &urls {
+            for to in &urls {
+                let resolved: String = stmt.query_row([from, to], |row| row.get(0))?;
+                assert_eq!(
+                    resolved,
+                    Url::parse(to).unwrap().to_string(),
+                    "{} -> {}",
+                    from,
+                    to
+                );
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,443 @@
+use rusqlite::{functions::FunctionFlags, Connection, Result};
+use serde_json::{Map, Value};
+
+use super::user_error;
+use crate::function_stats::create_scalar_function;
+
+/// Expression operators of RFC 6570, with the values of appendix A.
+struct Operator {
+    first: &'static str,
+    separator: &'static str,
+    named: bool,
+    if_empty: &'static str,
+    allow_reserved: bool,
+}
+
+const fn operator(
+    first: &'static str,
+    separator: &'static str,
+    named: bool,
+    if_empty: &'static str,
+    allow_reserved: bool,
+) -> Operator {
+    Operator {
+        first,
+        separator,
+        named,
+        if_empty,
+        allow_reserved,
+    }
+}
+
+fn operator_for(c: char) -> Option<&'static Operator> {
+    const SIMPLE: Operator = operator("", ",", false, "", false);
+    const RESERVED: Operator = operator("", ",", false, "", true);
+    const FRAGMENT: Operator = operator("#", ",", false, "", true);
+    const LABEL: Operator = operator(".", ".", false, "", false);
+    const PATH: Operator = operator("/", "/", false, "", false);
+    const PARAMETERS: Operator = operator(";", ";", true, "", false);
+    const QUERY: Operator = operator("?", "&", true, "=", false);
+    const CONTINUATION: Operator = operator("&", "&", true, "=", false);
+    match c {
+        '+' => Some(&RESERVED),
+        '#' => Some(&FRAGMENT),
+        '.' => Some(&LABEL),
+        '/' => Some(&PATH),
+        ';' => Some(&PARAMETERS),
+        '?' => Some(&QUERY),
+        '&' => Some(&CONTINUATION),
+        _ if c.is_ascii_alphanumeric() || c == '_' || c == '%' => Some(&SIMPLE),
+        _ => None,
+    }
+}
+
+fn is_unreserved(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_' | '~')
+}
+
+fn is_reserved(c: char) -> bool {
+    matches!(
+        c,
+        ':' | '/'
+            | '?'
+            | '#'
+            | '['
+            | ']'
+            | '@'
+            | '!'
+            | '$'
+            | '&'
+            | '\''
+            | '('
+            | ')'
+            | '*'
+            | '+'
+            | ','
+            | ';'
+            | '='
+    )
+}
+
+/// Percent-encodes `value`, leaving unreserved characters alone and, when `allow_reserved` is
+/// set, reserved characters and existing `%XX` triplets as well.
+fn encode(value: &str, allow_reserved: bool, out: &mut String) {
+    let bytes = value.as_bytes();
+    for (i, c) in value.char_indices() {
+        let triplet = c == '%'
+            && bytes.get(i + 1).is_some_and(u8::is_ascii_hexdigit)
+            && bytes.get(i + 2).is_some_and(u8::is_ascii_hexdigit);
+        if is_unreserved(c) || (allow_reserved && (is_reserved(c) || triplet)) {
+            out.push(c);
+        } else {
+            let mut buf = [0; 4];
+            for byte in c.encode_utf8(&mut buf).bytes() {
+                out.push_str(&format!("%{:02X}", byte));
+            }
+        }
+    }
+}
+
+/// Text of a scalar JSON value, `None` for null and composites.
+fn scalar_text(value: &Value) -> Option<String> {
+    match value {
+        Value::String(text) => Some(text.clone()),
+        Value::Number(number) => Some(number.to_string()),
+        Value::Bool(flag) => Some(flag.to_string()),
+        _ => None,
+    }
+}
+
+/// A variable of an expression, `name`, `name:3` or `name*`.
+struct VarSpec<'a> {
+    name: &'a str,
+    prefix: Option<usize>,
+    explode: bool,
+}
+
+impl<'a> VarSpec<'a> {
+    fn parse(spec: &'a str) -> std::result::Result<VarSpec<'a>, String> {
+        let invalid = || format!("invalid variable: {:?}", spec);
+        let (name, prefix, explode) = if let Some(name) = spec.strip_suffix('*') {
+            (name, None, true)
+        } else if let Some((name, length)) = spec.split_once(':') {
+            // 1 to 4 digits without a leading zero
+            let valid_length = (1..=4).contains(&length.len())
+                && !length.starts_with('0')
+                && length.bytes().all(|c| c.is_ascii_digit());
+            if !valid_length {
+                return Err(invalid());
+            }
+            (name, Some(length.parse().map_err(|_| invalid())?), false)
+        } else {
+            (spec, None, false)
+        };
+        let valid_name = !name.is_empty()
+            && !name.starts_with('.')
+            && !name.ends_with('.')
+            && !name.contains("..")
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '.' | '%'));
+        if !valid_name {
+            return Err(invalid());
+        }
+        Ok(VarSpec {
+            name,
+            prefix,
+            explode,
+        })
+    }
+}
+
+/// Expands one `{...}` expression, `expression` being the text between the braces.
+fn expand_expression(
+    expression: &str,
+    vars: &Map<String, Value>,
+    out: &mut String,
+) -> std::result::Result<(), String> {
+    let first = expression.chars().next().unwrap_or(' ');
+    let op =
+        operator_for(first).ok_or_else(|| format!("unsupported expression: {{{}}}", expression))?;
+    let var_list = match first {
+        '+' | '#' | '.' | '/' | ';' | '?' | '&' => &expression[1..],
+        _ => expression,
+    };
+
+    let mut defined = 0;
+    for spec in var_list.split(',') {
+        let spec = VarSpec::parse(spec)?;
+        let value = vars.get(spec.name).unwrap_or(&Value::Null);
+        let is_empty_composite = match value {
+            Value::Null => true,
+            Value::Array(items) => items.iter().all(|item| scalar_text(item).is_none()),
+            Value::Object(entries) => entries.values().all(|item| scalar_text(item).is_none()),
+            _ => false,
+        };
+        if is_empty_composite {
+            continue;
+        }
+
+        out.push_str(if defined == 0 { op.first } else { op.separator });
+        defined += 1;
+
+        if let Some(text) = scalar_text(value) {
+            if op.named {
+                out.push_str(spec.name);
+                if text.is_empty() {
+                    out.push_str(op.if_empty);
+                    continue;
+                }
+                out.push('=');
+            }
+            let text = match spec.prefix {
+                Some(length) => text.chars().take(length).collect(),
+                None => text,
+            };
+            encode(&text, op.allow_reserved, out);
+            continue;
+        }
+        if spec.prefix.is_some() {
+            return Err(format!(
+                "prefix modifier applied to list or object: {}",
+                spec.name
+            ));
+        }
+
+        // (name, value) pairs of a composite, `name` being None for list items
+        let pairs: Vec<(Option<&str>, String)> = match value {
+            Value::Array(items) => items
+                .iter()
+                .filter_map(scalar_text)
+                .map(|item| (None, item))
+                .collect(),
+            Value::Object(entries) => entries
+                .iter()
+                .filter_map(|(key, item)| Some((Some(key.as_str()), scalar_text(item)?)))
+                .collect(),
+            _ => unreachable!("scalars and nulls are handled above"),
+        };
+
+        if !spec.explode {
+            if op.named {
+                out.push_str(spec.name);
+                out.push('=');
+            }
+            for (i, (key, item)) in pairs.iter().enumerate() {
+                if i > 0 {
+                    out.push(',');
+                }
+                if let Some(key) = key {
+                    encode(key, op.allow_reserved, out);
+                    out.push(',');
+                }
+                encode(item, op.allow_reserved, out);
+            }
+            continue;
+        }
+
+        for (i, (key, item)) in pairs.iter().enumerate() {
+            if i > 0 {
+                out.push_str(op.separator);
+            }
+            match key {
+                Some(key) => encode(key, op.allow_reserved, out),
+                None if op.named => out.push_str(spec.name),
+                None => {
+                    encode(item, op.allow_reserved, out);
+                    continue;
+                }
+            }
+            if op.named && item.is_empty() {
+                out.push_str(op.if_empty);
+            } else {
+                out.push('=');
+                encode(item, op.allow_reserved, out);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Expands the RFC 6570 (levels 1 to 4) URI template `template` with the variables of `vars`.
+fn expand(template: &str, vars: &Map<String, Value>) -> std::result::Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(open) = rest.find(['{', '}']) {
+        if rest[open..].starts_with('}') {
+            return Err("unmatched '}'".to_string());
+        }
+        encode(&rest[..open], true, &mut out);
+        let close = rest[open..]
+            .find('}')
+            .map(|close| open + close)
+            .ok_or("unclosed expression")?;
+        expand_expression(&rest[open + 1..close], vars, &mut out)?;
+        rest = &rest[close + 1..];
+    }
+    encode(rest, true, &mut out);
+    Ok(out)
+}
+
+pub fn register_template_functions(conn: &Connection) -> Result<()> {
+    create_scalar_function(
+        conn,
+        "url_template",
+        2,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(template) = ctx.get::<Option<String>>(0)? else {
+                return Ok(None);
+            };
+            let vars = match ctx.get::<Option<String>>(1)? {
+                None => Map::new(),
+                Some(json) => match serde_json::from_str(&json) {
+                    Ok(Value::Object(vars)) => vars,
+                    Ok(_) => {
+                        return Err(user_error("url_template() variables must be a JSON object"))
+                    }
+                    Err(e) => return Err(user_error(format!("Invalid JSON: {}", e))),
+                },
+            };
+            expand(&template, &vars)
+                .map(Some)
+                .map_err(|err| user_error(format!("url_template(): {}", err)))
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Variables of the examples in RFC 6570, section 3.2.
+    fn rfc_vars() -> Map<String, Value> {
+        let Value::Object(vars) = json!({
+            "count": ["one", "two", "three"],
+            "dom": ["example", "com"],
+            "dub": "me/too",
+            "hello": "Hello World!",
+            "half": "50%",
+            "var": "value",
+            "who": "fred",
+            "base": "http://example.com/home/",
+            "path": "/foo/bar",
+            "list": ["red", "green", "blue"],
+            "keys": {"semi": ";", "dot": ".", "comma": ","},
+            "v": "6",
+            "x": "1024",
+            "y": "768",
+            "empty": "",
+            "empty_keys": {},
+            "undef": null,
+        }) else {
+            unreachable!()
+        };
+        vars
+    }
+
+    #[test]
+    fn test_rfc_6570_examples() {
+        let vars = rfc_vars();
+        for (template, expected) in [
+            // level 1
+            ("{var}", "value"),
+            ("{hello}", "Hello%20World%21"),
+            // level 2
+            ("{+var}", "value"),
+            ("{+hello}", "Hello%20World!"),
+            ("{+path}/here", "/foo/bar/here"),
+            ("here?ref={+path}", "here?ref=/foo/bar"),
+            ("X{#var}", "X#value"),
+            ("X{#hello}", "X#Hello%20World!"),
+            // level 3
+            ("map?{x,y}", "map?1024,768"),
+            ("{x,hello,y}", "1024,Hello%20World%21,768"),
+            ("{+x,hello,y}", "1024,Hello%20World!,768"),
+            ("{+path,x}/here", "/foo/bar,1024/here"),
+            ("{#x,hello,y}", "#1024,Hello%20World!,768"),
+            ("X{.var}", "X.value"),
+            ("X{.x,y}", "X.1024.768"),
+            ("{/var}", "/value"),
+            ("{/var,x}/here", "/value/1024/here"),
+            ("{;x,y}", ";x=1024;y=768"),
+            ("{;x,y,empty}", ";x=1024;y=768;empty"),
+            ("{?x,y}", "?x=1024&y=768"),
+            ("{?x,y,empty}", "?x=1024&y=768&empty="),
+            ("?fixed=yes{&x}", "?fixed=yes&x=1024"),
+            ("{&x,y,empty}", "&x=1024&y=768&empty="),
+            // level 4
+            ("{var:3}", "val"),
+            ("{var:30}", "value"),
+            ("{list}", "red,green,blue"),
+            ("{list*}", "red,green,blue"),
+            ("{keys}", "semi,%3B,dot,.,comma,%2C"),
+            ("{keys*}", "semi=%3B,dot=.,comma=%2C"),
+            ("{+path:6}/here", "/foo/b/here"),
+            ("{+list}", "red,green,blue"),
+            ("{+keys*}", "semi=;,dot=.,comma=,"),
+            ("{#keys}", "#semi,;,dot,.,comma,,"),
+            ("X{.list*}", "X.red.green.blue"),
+            ("X{.keys*}", "X.semi=%3B.dot=..comma=%2C"),
+            ("{/var:1,var}", "/v/value"),
+            ("{/list*}", "/red/green/blue"),
+            ("{/list*,path:4}", "/red/green/blue/%2Ffoo"),
+            ("{;hello:5}", ";hello=Hello"),
+            ("{;list}", ";list=red,green,blue"),
+            ("{;list*}", ";list=red;list=green;list=blue"),
+            ("{;keys*}", ";semi=%3B;dot=.;comma=%2C"),
+            ("{?var:3}", "?var=val"),
+            ("{?list}", "?list=red,green,blue"),
+            ("{?list*}", "?list=red&list=green&list=blue"),
+            ("{?keys*}", "?semi=%3B&dot=.&comma=%2C"),
+            ("{&list*}", "&list=red&list=green&list=blue"),
+            // undefined and empty values
+            ("{undef}{?undef,empty_keys*}", ""),
+            ("{+base}{half}", "http://example.com/home/50%25"),
+            ("{count*}", "one,two,three"),
+            ("{/count*}", "/one/two/three"),
+            ("{?dom*}", "?dom=example&dom=com"),
+            ("{+dub}/{who}", "me/too/fred"),
+        ] {
+            assert_eq!(
+                expand(template, &vars).as_deref(),
+                Ok(expected),
+                "{}",
+                template
+            );
+        }
+    }
+
+    #[test]
+    fn test_url_template() -> Result<()> {
+        let conn = Connection::open_in_memory()?;
+        register_template_functions(&conn)?;
+
+        let url: String = conn.query_row(
+            "SELECT url_template('https://api.example.com/evidence{/id}{?fields*,page}', json_object('id', 'a b', 'fields', json_array('name', 'size'), 'page', 2))",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(
+            url,
+            "https://api.example.com/evidence/a%20b?fields=name&fields=size&page=2"
+        );
+
+        for template in ["{var", "var}", "{=var}", "{var:0}", "{list:2}", "{va r}"] {
+            assert!(
+                conn.query_row(
+                    "SELECT url_template(?, '{\"list\": [1, 2]}')",
+                    [template],
+                    |_| Ok(())
+                )
+                .is_err(),
+                "{}",
+                template
+            );
+        }
+        assert!(conn
+            .query_row("SELECT url_template('{x}', '[1]')", [], |_| Ok(()))
+            .is_err());
+        Ok(())
+    }
+}
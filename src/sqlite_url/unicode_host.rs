@@ -0,0 +1,160 @@
+use percent_encoding::percent_decode_str;
+use rusqlite::{functions::FunctionFlags, Connection, Result};
+use unicode_security::{skeleton, MixedScript};
+use url::{Host, Url};
+
+use super::extraction::register_extractor;
+use super::user_error;
+use crate::function_stats::create_scalar_function;
+
+/// The domain of `url` as written, percent-decoded: hosts of non-special schemes are opaque and
+/// keep their non-ASCII characters percent-encoded.
+fn decoded_domain(url: &Url) -> Option<String> {
+    match url.host()? {
+        Host::Domain(domain) => Some(percent_decode_str(domain).decode_utf8_lossy().into_owned()),
+        Host::Ipv4(_) | Host::Ipv6(_) => None,
+    }
+}
+
+/// `domain` in its Unicode form after UTS #46 processing, punycode labels decoded.
+fn unicode_domain(domain: &str) -> Result<String> {
+    let (unicode, result) = idna::domain_to_unicode(domain);
+    result
+        .map(|_| unicode)
+        .map_err(|_| user_error(format!("Invalid domain: {}", domain)))
+}
+
+pub fn register_unicode_host_functions(conn: &Connection) -> Result<()> {
+    // both return IP addresses unchanged and "" for URLs without a host, like url_host
+    register_extractor(conn, "url_host_unicode", |url| match decoded_domain(url) {
+        Some(domain) => idna::domain_to_unicode(&domain).0,
+        None => url.host_str().unwrap_or("").to_string(),
+    })?;
+    register_extractor(conn, "url_host_ascii", |url| {
+        let host = url.host_str().unwrap_or("");
+        decoded_domain(url)
+            .and_then(|domain| idna::domain_to_ascii(&domain).ok())
+            .unwrap_or_else(|| host.to_string())
+    })?;
+
+    // UTS #39 skeleton of the Unicode form, equal for domains that are visually confusable
+    create_scalar_function(
+        conn,
+        "domain_confusable_skeleton",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(domain) = ctx.get::<Option<String>>(0)? else {
+                return Ok(None);
+            };
+            let unicode = unicode_domain(&domain)?;
+            Ok(Some(skeleton(&unicode).collect::<String>()))
+        },
+    )?;
+
+    // a label mixing scripts, such as Latin and Cyrillic, is a usual homoglyph attack; domains
+    // whose labels are each in a single script (`пример.com`) are not flagged
+    create_scalar_function(
+        conn,
+        "domain_is_mixed_script",
+        1,
+        FunctionFlags::SQLITE_DETERMINISTIC,
+        |ctx| {
+            let Some(domain) = ctx.get::<Option<String>>(0)? else {
+                return Ok(None);
+            };
+            let unicode = unicode_domain(&domain)?;
+            Ok(Some(
+                unicode.split('.').any(|label| !label.is_single_script()),
+            ))
+        },
+    )?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusqlite::Connection;
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_unicode_host_functions(&conn).unwrap();
+        conn
+    }
+
+    fn text(conn: &Connection, sql: &str, arg: &str) -> Option<String> {
+        conn.query_row(sql, [arg], |row| row.get(0)).unwrap()
+    }
+
+    #[test]
+    fn test_url_host_unicode_and_ascii() {
+        let conn = setup_connection();
+        for (url, unicode, ascii) in [
+            (
+                "https://xn--mnchen-3ya.de/",
+                "münchen.de",
+                "xn--mnchen-3ya.de",
+            ),
+            ("https://MÜNCHEN.de/", "münchen.de", "xn--mnchen-3ya.de"),
+            ("myapp://münchen.de/", "münchen.de", "xn--mnchen-3ya.de"),
+            ("https://example.com/", "example.com", "example.com"),
+            ("http://[::1]/", "[::1]", "[::1]"),
+            ("mailto:someone@example.com", "", ""),
+        ] {
+            assert_eq!(
+                text(&conn, "SELECT url_host_unicode(?)", url).as_deref(),
+                Some(unicode),
+                "{}",
+                url
+            );
+            assert_eq!(
+                text(&conn, "SELECT url_host_ascii(?)", url).as_deref(),
+                Some(ascii),
+                "{}",
+                url
+            );
+        }
+        assert_eq!(
+            text(&conn, "SELECT url_try_host_unicode(?)", "invalid"),
+            None
+        );
+        assert!(conn
+            .query_row("SELECT url_host_unicode('invalid')", [], |_| Ok(()))
+            .is_err());
+    }
+
+    #[test]
+    fn test_domain_confusables() {
+        let conn = setup_connection();
+        let skeleton = |domain: &str| text(&conn, "SELECT domain_confusable_skeleton(?)", domain);
+        let is_mixed = |domain: &str| -> bool {
+            conn.query_row("SELECT domain_is_mixed_script(?)", [domain], |row| {
+                row.get(0)
+            })
+            .unwrap()
+        };
+
+        // Cyrillic "а" in place of the Latin one
+        assert_eq!(skeleton("xn--pple-43d.com"), skeleton("apple.com"));
+        assert_eq!(skeleton("аpple.com"), skeleton("APPLE.com"));
+        assert_ne!(skeleton("apple.com"), skeleton("appie.org"));
+        assert!(is_mixed("xn--pple-43d.com"));
+        assert!(!is_mixed("apple.com"));
+        assert!(!is_mixed("пример.com"));
+        assert!(!is_mixed("münchen-2024.de"));
+
+        let null: Option<bool> = conn
+            .query_row("SELECT domain_is_mixed_script(NULL)", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(null, None);
+        assert!(conn
+            .query_row(
+                "SELECT domain_confusable_skeleton('xn--a.com')",
+                [],
+                |_| Ok(())
+            )
+            .is_err());
+    }
+}
@@ -0,0 +1,159 @@
+use rusqlite::{types::Value, Connection, Error, Result};
+use std::ops::Range;
+use url::Url;
+
+use crate::{register_table_function, Rows, TableFunction};
+
+/// Splits `text` into its components following the generic syntax of RFC 3986 (appendix B),
+/// returning byte ranges. Components that are absent are left out, `?` and `#` followed by
+/// nothing give an empty query or fragment. In the `special` schemes a `\` is read as a `/`, as
+/// the URL parser does.
+fn split_components(text: &str, special: bool) -> Vec<(&'static str, Range<usize>)> {
+    let slashes: &[char] = if special { &['/', '\\'] } else { &['/'] };
+    let authority_end: &[char] = if special {
+        &['/', '\\', '?', '#']
+    } else {
+        &['/', '?', '#']
+    };
+    let mut components = vec![];
+    let end_of = |from: usize, delimiters: &[char]| {
+        text[from..]
+            .find(delimiters)
+            .map_or(text.len(), |offset| from + offset)
+    };
+
+    let mut pos = 0;
+    if let Some(colon) = text.find([':', '/', '?', '#']) {
+        if colon > 0 && text[colon..].starts_with(':') {
+            components.push(("scheme", 0..colon));
+            pos = colon + 1;
+        }
+    }
+
+    if text[pos..].starts_with(slashes) && text[pos + 1..].starts_with(slashes) {
+        let start = pos + 2;
+        let end = end_of(start, authority_end);
+        let mut host_start = start;
+        if let Some(at) = text[start..end].rfind('@') {
+            let at = start + at;
+            match text[start..at].find(':') {
+                Some(colon) => {
+                    components.push(("user", start..start + colon));
+                    components.push(("password", start + colon + 1..at));
+                }
+                None => components.push(("user", start..at)),
+            }
+            host_start = at + 1;
+        }
+        // the port follows the last colon, unless it is inside an IPv6 literal
+        let port_colon = text[host_start..end]
+            .rfind(':')
+            .map(|colon| host_start + colon)
+            .filter(|&colon| !text[colon..end].contains(']'));
+        match port_colon {
+            Some(colon) => {
+                components.push(("host", host_start..colon));
+                components.push(("port", colon + 1..end));
+            }
+            None => components.push(("host", host_start..end)),
+        }
+        pos = end;
+    }
+
+    let path_end = end_of(pos, &['?', '#']);
+    if path_end > pos {
+        components.push(("path", pos..path_end));
+    }
+    pos = path_end;
+
+    if text[pos..].starts_with('?') {
+        let end = end_of(pos + 1, &['#']);
+        components.push(("query", pos + 1..end));
+        pos = end;
+    }
+    if text[pos..].starts_with('#') {
+        components.push(("fragment", pos + 1..text.len()));
+    }
+    components
+}
+
+/// Whether `components`, split from `text`, are the ones `url` was parsed into. The splitter
+/// only knows the generic syntax, so it reads `https:example.com` as a path where the parser
+/// finds a host, and it can't account for the tabs and newlines the parser drops.
+fn agrees_with(components: &[(&str, Range<usize>)], text: &str, url: &Url) -> bool {
+    let written = |name: &str| {
+        components
+            .iter()
+            .find(|(component, _)| *component == name)
+            .map(|(_, range)| &text[range.clone()])
+    };
+    let non_empty = |value: Option<&str>| value.is_some_and(|value| !value.is_empty());
+
+    let port_agrees = match written("port").filter(|port| !port.is_empty()) {
+        Some(port) => port.parse::<u16>().ok() == url.port_or_known_default(),
+        None => url.port().is_none(),
+    };
+    !text.contains(['\t', '\n', '\r'])
+        && non_empty(written("host")) == non_empty(url.host_str())
+        && non_empty(written("user")) == non_empty(Some(url.username()))
+        && non_empty(written("password")) == non_empty(url.password())
+        && port_agrees
+        && written("query").is_some() == url.query().is_some()
+        && written("fragment").is_some() == url.fragment().is_some()
+}
+
+/// `url_each(url)`: one `(name, value, start, end)` row per component of `url`, as written.
+/// `start` and `end` are 0-based character offsets into `url`, `end` exclusive, so
+/// `substr(url, start + 1, end - start)` is `value`. URLs the parser reads differently from how
+/// they are written, such as `https:example.com`, are rejected.
+struct UrlEach;
+
+impl TableFunction for UrlEach {
+    const COLUMNS: &'static [&'static str] =
+        &["name text", "value text", "start integer", "end integer"];
+    const ARGUMENTS: &'static [&'static str] = &["url"];
+    const FIRST_ROWID: i64 = 0;
+
+    fn rows(&self, args: &[Value]) -> Result<Rows> {
+        let Value::Text(url_text) = &args[0] else {
+            return Err(Error::ModuleError("url must be text.".to_string()));
+        };
+        let url = Url::parse(url_text)
+            .map_err(|err| Error::ModuleError(format!("invalid url: {}", err)))?;
+
+        // the parser ignores leading and trailing C0 controls and spaces
+        let text = url_text.trim_start_matches(|c: char| c <= ' ');
+        let offset = url_text.len() - text.len();
+        let text = text.trim_end_matches(|c: char| c <= ' ');
+
+        let special = matches!(
+            url.scheme(),
+            "http" | "https" | "ws" | "wss" | "ftp" | "file"
+        );
+        let components = split_components(text, special);
+        if !agrees_with(&components, text, &url) {
+            return Err(Error::ModuleError(format!(
+                "can't split {:?} into components as written, it is parsed as {}",
+                url_text, url
+            )));
+        }
+
+        let chars_before = |byte: usize| url_text[..offset + byte].chars().count() as i64;
+        let rows: Vec<Result<Vec<Value>>> = components
+            .into_iter()
+            .map(|(name, range)| {
+                Ok(vec![
+                    Value::Text(name.to_string()),
+                    Value::Text(text[range.clone()].to_string()),
+                    Value::Integer(chars_before(range.start)),
+                    Value::Integer(chars_before(range.end)),
+                ])
+            })
+            .collect();
+        Ok(Box::new(rows.into_iter()))
+    }
+}
+
+pub fn register_url_each_virtual_table(conn: &Connection) -> Result<()> {
+    register_table_function(conn, "url_each", UrlEach)
+}
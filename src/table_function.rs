@@ -0,0 +1,373 @@
+//! Table-valued functions without the `VTab`/`VTabCursor` boilerplate.
+//!
+//! Implement [`TableFunction`] and register it with [`register_table_function`]:
+//!
+//! ```
+//! use rusqlite::{types::Value, Connection, Result};
+//! use surveilr_extensions::{register_table_function, Rows, TableFunction};
+//!
+//! struct Repeat;
+//!
+//! impl TableFunction for Repeat {
+//!     const COLUMNS: &'static [&'static str] = &["value text"];
+//!     const ARGUMENTS: &'static [&'static str] = &["text", "times"];
+//!
+//!     fn rows(&self, args: &[Value]) -> Result<Rows> {
+//!         let times = match args[1] {
+//!             Value::Integer(times) => times as usize,
+//!             _ => 1,
+//!         };
+//!         Ok(Box::new(std::iter::repeat(args[0].clone()).take(times).map(|text| Ok(vec![text]))))
+//!     }
+//! }
+//!
+//! let conn = Connection::open_in_memory()?;
+//! register_table_function(&conn, "repeat", Repeat)?;
+//! let count: i64 = conn.query_row("SELECT count(*) FROM repeat('a', 3)", [], |row| row.get(0))?;
+//! assert_eq!(count, 3);
+//! # Ok::<(), rusqlite::Error>(())
+//! ```
+
+use rusqlite::{
+    ffi,
+    types::Value,
+    vtab::{
+        self, eponymous_only_module, CreateVTab, IndexInfo, VTab, VTabConfig, VTabCursor, VTabKind,
+    },
+    Connection, Error, Result,
+};
+use std::os::raw::c_int;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::function_stats::{module_stats, record_callback, row_bytes, value_bytes, FunctionStats};
+use crate::vtab::{bind_hidden_columns, hidden_column_args};
+
+/// Rows produced by a [`TableFunction`] call, one value per entry of
+/// [`COLUMNS`](TableFunction::COLUMNS).
+pub type Rows = Box<dyn Iterator<Item = Result<Vec<Value>>>>;
+
+/// A table-valued function: hidden input columns in, an iterator of rows out.
+///
+/// `SELECT * FROM name(a, b)` binds `a` and `b` to [`ARGUMENTS`](TableFunction::ARGUMENTS) in
+/// order; so does `WHERE arg = ...` on the hidden columns. Only `=` constraints are handed to
+/// [`rows`](TableFunction::rows), everything else is left to SQLite.
+pub trait TableFunction: 'static {
+    /// Output column declarations, e.g. `"name text"`.
+    const COLUMNS: &'static [&'static str];
+    /// Names of the hidden input columns, in argument order.
+    const ARGUMENTS: &'static [&'static str];
+    /// How many leading arguments must be given; calls without them fail. Capped at the number
+    /// of [`ARGUMENTS`](TableFunction::ARGUMENTS), so functions without any need not lower it.
+    const REQUIRED: usize = 1;
+    /// Rowid of the first row.
+    const FIRST_ROWID: i64 = 1;
+    /// Whether the function may only be used in top-level SQL, not in views or triggers.
+    const DIRECT_ONLY: bool = false;
+
+    /// Produces the rows for one call. `args` has one value per
+    /// [`ARGUMENTS`](TableFunction::ARGUMENTS) entry, NULL for optional ones that were not given.
+    fn rows(&self, args: &[Value]) -> Result<Rows>;
+}
+
+/// Registers `function` on `conn` as the eponymous virtual table `name`. Its calls are counted
+/// by [`enable_function_stats`](crate::enable_function_stats) when it was enabled before.
+pub fn register_table_function<T: TableFunction>(
+    conn: &Connection,
+    name: &str,
+    function: T,
+) -> Result<()> {
+    conn.create_module(
+        name,
+        eponymous_only_module::<TableFunctionTable<T>>(),
+        Some(TableFunctionAux {
+            function: Arc::new(function),
+            stats: module_stats(conn, name, T::ARGUMENTS.len()),
+        }),
+    )
+}
+
+struct TableFunctionAux<T> {
+    function: Arc<T>,
+    stats: Option<Arc<FunctionStats>>,
+}
+
+#[repr(C)]
+struct TableFunctionTable<T> {
+    base: ffi::sqlite3_vtab,
+    function: Arc<T>,
+    stats: Option<Arc<FunctionStats>>,
+}
+
+unsafe impl<'vtab, T: TableFunction> VTab<'vtab> for TableFunctionTable<T> {
+    type Aux = TableFunctionAux<T>;
+    type Cursor = TableFunctionCursor<T>;
+
+    fn connect(
+        db: &mut vtab::VTabConnection,
+        aux: Option<&Self::Aux>,
+        _args: &[&[u8]],
+    ) -> Result<(String, Self)> {
+        let Some(TableFunctionAux { function, stats }) = aux else {
+            return Err(Error::ModuleError(
+                "table function registered without an implementation".to_string(),
+            ));
+        };
+        if T::DIRECT_ONLY {
+            db.config(VTabConfig::DirectOnly)?;
+        }
+
+        let columns: Vec<String> = T::COLUMNS
+            .iter()
+            .map(|column| column.to_string())
+            .chain(T::ARGUMENTS.iter().map(|arg| format!("{} hidden", arg)))
+            .collect();
+        let schema = format!("CREATE TABLE x({})", columns.join(", "));
+        Ok((
+            schema,
+            TableFunctionTable {
+                base: ffi::sqlite3_vtab::default(),
+                function: function.clone(),
+                stats: stats.clone(),
+            },
+        ))
+    }
+
+    fn best_index(&self, info: &mut IndexInfo) -> Result<()> {
+        bind_hidden_columns(info, T::COLUMNS.len() as c_int, T::ARGUMENTS.len())
+    }
+
+    fn open(&mut self) -> Result<Self::Cursor> {
+        Ok(TableFunctionCursor {
+            base: ffi::sqlite3_vtab_cursor::default(),
+            function: self.function.clone(),
+            stats: self.stats.clone(),
+            args: vec![],
+            rows: None,
+            row: None,
+            rowid: 0,
+        })
+    }
+}
+
+impl<T: TableFunction> CreateVTab<'_> for TableFunctionTable<T> {
+    const KIND: VTabKind = VTabKind::EponymousOnly;
+}
+
+#[repr(C)]
+struct TableFunctionCursor<T> {
+    base: ffi::sqlite3_vtab_cursor,
+    function: Arc<T>,
+    stats: Option<Arc<FunctionStats>>,
+    args: Vec<Value>,
+    rows: Option<Rows>,
+    row: Option<Vec<Value>>,
+    rowid: i64,
+}
+
+unsafe impl<T: TableFunction> VTabCursor for TableFunctionCursor<T> {
+    fn filter(
+        &mut self,
+        idx_num: c_int,
+        _idx_str: Option<&str>,
+        args: &vtab::Values<'_>,
+    ) -> Result<()> {
+        let positions = hidden_column_args(idx_num, T::ARGUMENTS.len());
+        let required = &positions[..T::REQUIRED.min(positions.len())];
+        if let Some(missing) = required.iter().position(Option::is_none) {
+            return Err(Error::ModuleError(format!(
+                "Missing required {} argument.",
+                T::ARGUMENTS[missing]
+            )));
+        }
+
+        self.args = positions
+            .into_iter()
+            .map(|position| match position {
+                Some(arg) => args.get::<Value>(arg),
+                None => Ok(Value::Null),
+            })
+            .collect::<Result<_>>()?;
+        let start = Instant::now();
+        let rows = self.function.rows(&self.args);
+        let bytes_in = self.args.iter().map(|arg| value_bytes(arg.into())).sum();
+        record_callback(&self.stats, true, start, bytes_in, 0, rows.is_err());
+        self.rows = Some(rows?);
+        self.rowid = T::FIRST_ROWID - 1;
+        self.next()
+    }
+
+    fn next(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let row = match self.rows.as_mut() {
+            Some(rows) => rows.next().transpose(),
+            None => Ok(None),
+        };
+        let bytes_out = match &row {
+            Ok(Some(row)) => row_bytes(row),
+            _ => 0,
+        };
+        record_callback(&self.stats, false, start, 0, bytes_out, row.is_err());
+        self.row = row?;
+        self.rowid += 1;
+        Ok(())
+    }
+
+    fn eof(&self) -> bool {
+        self.row.is_none()
+    }
+
+    fn column(&self, ctx: &mut vtab::Context, col: i32) -> Result<()> {
+        let col = col as usize;
+        let value = if col < T::COLUMNS.len() {
+            self.row.as_ref().and_then(|row| row.get(col))
+        } else {
+            self.args.get(col - T::COLUMNS.len())
+        };
+        ctx.set_result(value.unwrap_or(&Value::Null))
+    }
+
+    fn rowid(&self) -> Result<i64> {
+        Ok(self.rowid)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Split;
+
+    impl TableFunction for Split {
+        const COLUMNS: &'static [&'static str] = &["part text"];
+        const ARGUMENTS: &'static [&'static str] = &["input", "separator"];
+
+        fn rows(&self, args: &[Value]) -> Result<Rows> {
+            let Value::Text(input) = &args[0] else {
+                return Ok(Box::new(std::iter::empty()));
+            };
+            let separator = match &args[1] {
+                Value::Text(separator) => separator.clone(),
+                _ => ",".to_string(),
+            };
+            let parts: Vec<Result<Vec<Value>>> = input
+                .split(separator.as_str())
+                .map(|part| Ok(vec![Value::Text(part.to_string())]))
+                .collect();
+            Ok(Box::new(parts.into_iter()))
+        }
+    }
+
+    /// Counts up to its argument, failing on negative ones after the first row.
+    struct Count;
+
+    impl TableFunction for Count {
+        const COLUMNS: &'static [&'static str] = &["value integer"];
+        const ARGUMENTS: &'static [&'static str] = &["stop"];
+        const DIRECT_ONLY: bool = true;
+
+        fn rows(&self, args: &[Value]) -> Result<Rows> {
+            let stop = match args[0] {
+                Value::Integer(stop) => stop,
+                _ => 0,
+            };
+            if stop < 0 {
+                return Ok(Box::new(
+                    vec![
+                        Ok(vec![Value::Integer(1)]),
+                        Err(Error::ModuleError("stop must not be negative".to_string())),
+                    ]
+                    .into_iter(),
+                ));
+            }
+            Ok(Box::new(
+                (1..=stop).map(|value| Ok(vec![Value::Integer(value)])),
+            ))
+        }
+    }
+
+    /// No arguments, but the default `REQUIRED`.
+    struct Constant;
+
+    impl TableFunction for Constant {
+        const COLUMNS: &'static [&'static str] = &["value integer"];
+        const ARGUMENTS: &'static [&'static str] = &[];
+
+        fn rows(&self, _args: &[Value]) -> Result<Rows> {
+            Ok(Box::new(std::iter::once(Ok(vec![Value::Integer(42)]))))
+        }
+    }
+
+    fn setup_connection() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        register_table_function(&conn, "split", Split).unwrap();
+        register_table_function(&conn, "count_to", Count).unwrap();
+        register_table_function(&conn, "constant", Constant).unwrap();
+        conn
+    }
+
+    fn split(conn: &Connection, sql: &str) -> Result<Vec<(i64, String, String)>> {
+        conn.prepare(sql)?
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+            .collect()
+    }
+
+    #[test]
+    fn test_table_function_arguments() -> Result<()> {
+        let conn = setup_connection();
+        assert_eq!(
+            split(&conn, "SELECT rowid, part, input FROM split('a,b')")?,
+            vec![
+                (1, "a".to_string(), "a,b".to_string()),
+                (2, "b".to_string(), "a,b".to_string()),
+            ]
+        );
+        assert_eq!(
+            split(
+                &conn,
+                "SELECT rowid, part, separator FROM split WHERE separator = '|' AND input = 'a|b'"
+            )?,
+            vec![
+                (1, "a".to_string(), "|".to_string()),
+                (2, "b".to_string(), "|".to_string()),
+            ]
+        );
+
+        let columns: i64 = conn.query_row(
+            "SELECT count(*) FROM pragma_table_info('split')",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(columns, 1);
+        assert!(split(&conn, "SELECT rowid, part, input FROM split").is_err());
+
+        let constant: i64 = conn.query_row("SELECT value FROM constant", [], |row| row.get(0))?;
+        assert_eq!(constant, 42);
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_function_lateral_join() -> Result<()> {
+        let conn = setup_connection();
+        let count: i64 = conn.query_row(
+            "SELECT count(*) FROM (SELECT 'a,b' AS s UNION ALL SELECT 'c') AS t, split(t.s)",
+            [],
+            |row| row.get(0),
+        )?;
+        assert_eq!(count, 3);
+        Ok(())
+    }
+
+    #[test]
+    fn test_table_function_errors_and_direct_only() -> Result<()> {
+        let conn = setup_connection();
+        let sum = |sql: &str| conn.query_row(sql, [], |row| row.get::<_, i64>(0));
+
+        assert_eq!(sum("SELECT sum(value) FROM count_to(3)")?, 6);
+        assert!(sum("SELECT sum(value) FROM count_to(-1)").is_err());
+
+        conn.execute_batch("CREATE VIEW v AS SELECT value FROM count_to(3)")?;
+        assert!(sum("SELECT sum(value) FROM v").is_err());
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use rusqlite::vtab::{IndexConstraintOp, IndexInfo};
+use rusqlite::Result;
+use std::os::raw::c_int;
+
+/// Hands usable `=` constraints on the hidden columns `first..first + count` to `filter` as
+/// arguments, in column order, and records in `idx_num` which of them are present (bit `i` for
+/// hidden column `i`) so optional arguments can be told apart.
+pub(crate) fn bind_hidden_columns(info: &mut IndexInfo, first: c_int, count: usize) -> Result<()> {
+    let mut constraint_for_column: Vec<Option<usize>> = vec![None; count];
+    for (i, constraint) in info.constraints().enumerate() {
+        let column = constraint.column() - first;
+        if column >= 0
+            && (column as usize) < count
+            && constraint.is_usable()
+            && constraint.operator() == IndexConstraintOp::SQLITE_INDEX_CONSTRAINT_EQ
+        {
+            constraint_for_column[column as usize] = Some(i);
+        }
+    }
+
+    let mut idx_num = 0;
+    let mut argv_index = 0;
+    for (column, constraint) in constraint_for_column.into_iter().enumerate() {
+        if let Some(constraint) = constraint {
+            argv_index += 1;
+            idx_num |= 1 << column;
+            let mut usage = info.constraint_usage(constraint);
+            usage.set_argv_index(argv_index);
+            usage.set_omit(true);
+        }
+    }
+
+    info.set_idx_num(idx_num);
+    info.set_estimated_cost(if idx_num & 1 != 0 { 1.0 } else { 1e9 });
+    Ok(())
+}
+
+/// Position in `filter`'s arguments of each hidden column bound by [`bind_hidden_columns`].
+pub(crate) fn hidden_column_args(idx_num: c_int, count: usize) -> Vec<Option<usize>> {
+    let mut next = 0;
+    (0..count)
+        .map(|column| {
+            (idx_num & (1 << column) != 0).then(|| {
+                next += 1;
+                next - 1
+            })
+        })
+        .collect()
+}